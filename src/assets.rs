@@ -0,0 +1,108 @@
+//! Tracking of which files under `content_path` are actually referenced
+
+use std::path::{Path, PathBuf, Component};
+use std::collections::BTreeMap;
+use crate::{Error, Result};
+
+/// How a referenced file ends up in the generated website
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AssetUsage {
+    /// The file is consumed during generation, eg. markdown, templates, or
+    /// assets which are embedded into the HTML
+    Embedded,
+
+    /// The file is linked to from the HTML, and thus must be copied to the
+    /// output directory
+    Copied,
+}
+
+/// Lexically normalize `path`, removing `.` and resolving `..` components
+/// without touching the filesystem
+pub fn normalize_path(path: impl AsRef<Path>) -> PathBuf {
+    let mut ret = PathBuf::new();
+    for component in path.as_ref().components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !ret.pop() {
+                    ret.push("..");
+                }
+            }
+            _ => ret.push(component),
+        }
+    }
+    ret
+}
+
+/// Determine if a markdown link destination refers to a local file rather
+/// than an external URL
+pub fn is_local(dest: &str) -> bool {
+    !dest.is_empty() && !dest.starts_with('#') && !dest.starts_with("//") &&
+        !dest.contains(':')
+}
+
+/// Resolve a local link destination `dest` which is found in `source` into a
+/// path on disk. Absolute destinations are relative to `content_path`, others
+/// are relative to the directory holding `source`
+pub fn resolve_local(content_path: &Path, source: &Path, dest: &str)
+        -> PathBuf {
+    // Strip any fragment or query string
+    let dest = dest.split(['#', '?']).next().unwrap();
+
+    if let Some(dest) = dest.strip_prefix('/') {
+        normalize_path(content_path.join(dest))
+    } else {
+        normalize_path(source.parent().unwrap_or(content_path).join(dest))
+    }
+}
+
+/// Copy all assets which are `AssetUsage::Copied` into the output directory,
+/// returning the paths they were copied to
+pub async fn copy_assets(assets: &BTreeMap<PathBuf, AssetUsage>,
+        content_path: &Path, output_path: &Path) -> Result<Vec<PathBuf>> {
+    let content_path = normalize_path(content_path);
+    let mut copied = Vec::new();
+
+    for (asset, _) in assets.iter().filter(|x| *x.1 == AssetUsage::Copied) {
+        let output = output_path.join(asset.strip_prefix(&content_path)
+            .map_err(|x| Error::StripPrefix(asset.clone(), x))?);
+
+        let out_parent_dir = output.parent().unwrap();
+        tokio::fs::create_dir_all(out_parent_dir).await
+            .map_err(|x|
+                Error::CreateOutputDir(out_parent_dir.to_path_buf(), x))?;
+        tokio::fs::copy(asset, &output).await
+            .map_err(|x| Error::CopyAsset(asset.clone(), output.clone(), x))?;
+        copied.push(output);
+    }
+
+    Ok(copied)
+}
+
+/// Get a sorted list of all files under `content_path` which are not in
+/// `assets`
+pub async fn unused_files(assets: &BTreeMap<PathBuf, AssetUsage>,
+        content_path: &Path) -> Result<Vec<PathBuf>> {
+    let mut unused = Vec::new();
+
+    // Walk the content directory
+    let mut dirs = vec![normalize_path(content_path)];
+    while let Some(path) = dirs.pop() {
+        let mut dir = tokio::fs::read_dir(&path).await.map_err(|x|
+            Error::ReadDirectory(path.clone(), x))?;
+        while let Some(dirent) = dir.next_entry().await.map_err(|x|
+                Error::ReadDirectory(path.clone(), x))? {
+            let file_type = dirent.file_type().await.map_err(|x|
+                Error::ReadDirectory(path.clone(), x))?;
+
+            if file_type.is_dir() {
+                dirs.push(dirent.path());
+            } else if !assets.contains_key(&dirent.path()) {
+                unused.push(dirent.path());
+            }
+        }
+    }
+
+    unused.sort();
+    Ok(unused)
+}
@@ -0,0 +1,480 @@
+//! Built-in handlers which render binary data, like hexdumps and disassembly
+
+use std::sync::Arc;
+use std::path::PathBuf;
+use std::collections::BTreeMap;
+use async_trait::async_trait;
+use serde_derive::Deserialize;
+use pulldown_cmark::escape::escape_html;
+use syntect::easy::HighlightLines;
+use syntect::highlighting::Highlighter;
+use syntect::html::{styled_line_to_highlighted_html, IncludeBackground};
+use syntect::parsing::Scope;
+use iced_x86::{Decoder, DecoderOptions, Formatter, Instruction};
+use iced_x86::{IntelFormatter, GasFormatter, SymbolResolver, SymbolResult};
+use crate::{Error, Result, Website, Handler, HandlerContext};
+use crate::handlers::{handler_template, resolve_include, parse_config};
+use crate::handlers::{css_color, theme_colors, theme_palette};
+
+/// Where the bytes for a block come from, either a file or inline hex
+#[derive(Debug, Deserialize)]
+struct BytesConfig {
+    /// File to read the bytes from, relative to `config.content_path`
+    file: Option<PathBuf>,
+
+    /// The bytes as hex, eg. `"4d 5a 90 00"`, used instead of `file`
+    hex: Option<String>,
+
+    /// Offset of the first byte to use
+    #[serde(default)]
+    offset: usize,
+
+    /// Number of bytes to use, defaults to everything after `offset`
+    length: Option<usize>,
+}
+
+/// Parse `hex` into bytes, ignoring whitespace between the digits, or
+/// describe what's wrong with it
+fn parse_hex(hex: &str) -> std::result::Result<Vec<u8>, String> {
+    let digits = hex.chars().filter(|x| !x.is_whitespace())
+        .collect::<Vec<_>>();
+    if let Some(bad) = digits.iter().find(|x| !x.is_ascii_hexdigit()) {
+        return Err(format!("`{}` isn't a hex digit", bad));
+    }
+    if digits.len() % 2 != 0 {
+        return Err(format!("{} hex digits is not a whole number of bytes",
+            digits.len()));
+    }
+
+    Ok(digits.chunks(2).map(|x| {
+        (x[0].to_digit(16).unwrap() << 4 | x[1].to_digit(16).unwrap()) as u8
+    }).collect())
+}
+
+/// Load the bytes `config` describes for the handler `name`, returning the
+/// offset of the first one and the bytes
+async fn load_bytes(website: &Website, ctx: &mut HandlerContext<'_>,
+        name: &str, config: &BytesConfig) -> Result<(usize, Vec<u8>)> {
+    let bad_input = |ctx: &HandlerContext, message: String|
+        Error::BadHandlerInput(ctx.source.to_path_buf(), name.into(), message);
+
+    let bytes = match (&config.file, &config.hex) {
+        (Some(file), None) => {
+            let (path, _) = resolve_include(website, file).await?;
+            ctx.add_dependency(&path);
+            tokio::fs::read(&path).await
+                .map_err(|x| Error::ReadBinary(path, x))?
+        }
+        (None, Some(hex)) => parse_hex(hex).map_err(|x| bad_input(ctx, x))?,
+        _ => return Err(bad_input(ctx,
+            "exactly one of `file` and `hex` is needed".into())),
+    };
+
+    // Cut out the window we want
+    let end = match config.length {
+        Some(length) => config.offset.checked_add(length),
+        None         => Some(bytes.len()),
+    };
+    match end {
+        Some(end) if config.offset <= end && end <= bytes.len() =>
+            Ok((config.offset, bytes[config.offset..end].to_vec())),
+        _ => Err(bad_input(ctx, format!("{:#x} bytes starting at {:#x} is \
+            past the end of the {:#x} bytes", end.unwrap_or(usize::MAX)
+            .saturating_sub(config.offset), config.offset, bytes.len()))),
+    }
+}
+
+/// Renders bytes as an offset, hex, and ASCII table, eg.
+///
+/// ```toml
+/// file = "blobs/boot.bin"
+/// offset = 0x1fe
+/// length = 2
+/// width = 16
+/// highlight = [{ start = 0x1fe, length = 2, label = "Boot signature" }]
+/// ```
+#[derive(Default)]
+pub(crate) struct Hexdump;
+
+#[derive(Debug, Deserialize)]
+struct HexdumpConfig {
+    /// The bytes to dump
+    #[serde(flatten)]
+    bytes: BytesConfig,
+
+    /// Number of bytes in each row, up to [`MAX_WIDTH`]
+    #[serde(default = "default_width")]
+    width: usize,
+
+    /// Regions to color and label
+    #[serde(default)]
+    highlight: Vec<HexdumpRegion>,
+}
+
+/// Default number of bytes per row of a hexdump
+fn default_width() -> usize {
+    16
+}
+
+/// Most bytes in a row of a hexdump
+const MAX_WIDTH: usize = 256;
+
+/// A labelled region of a hexdump
+#[derive(Debug, Deserialize)]
+struct HexdumpRegion {
+    /// Offset of the first byte in the region, in the same terms as `offset`
+    start: usize,
+
+    /// Number of bytes in the region
+    #[serde(default = "default_region_length")]
+    length: usize,
+
+    /// What the region is
+    label: String,
+}
+
+/// Default number of bytes in a highlighted region of a hexdump
+fn default_region_length() -> usize {
+    1
+}
+
+#[async_trait]
+impl Handler for Hexdump {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let config: HexdumpConfig = parse_config(ctx, "hexdump", input)?;
+        if config.width == 0 || config.width > MAX_WIDTH {
+            return Err(Error::BadHandlerInput(ctx.source.to_path_buf(),
+                "hexdump".into(), format!("`width` must be from 1 to {}",
+                    MAX_WIDTH)));
+        }
+        let (base, bytes) =
+            load_bytes(website, ctx, "hexdump", &config.bytes).await?;
+
+        // Give each region a color from the syntax theme
+        let colors = theme_palette(website);
+        let region_of = |offset: usize| config.highlight.iter()
+            .position(|x| offset >= x.start && offset - x.start < x.length);
+
+        // Make all the offsets the same width
+        let last = base + bytes.len().saturating_sub(1);
+        let digits = format!("{:x}", last).len().max(8);
+
+        let mut rows = String::new();
+        let row_template =
+            handler_template(website, ctx, "hexdump_row.html").await?;
+        for (ii, row) in bytes.chunks(config.width).enumerate() {
+            let offset = ii.checked_mul(config.width)
+                .and_then(|x| x.checked_add(base))
+                .ok_or_else(|| Error::BadHandlerInput(
+                    ctx.source.to_path_buf(), "hexdump".into(),
+                    "offsets don't fit in an address".into()))?;
+            let mut hex = String::new();
+            let mut ascii = String::new();
+
+            // Runs of bytes in the same region share a span
+            let mut cur_region = None;
+            for (jj, &byte) in row.iter().enumerate() {
+                let region = region_of(offset + jj);
+                if region != cur_region && cur_region.is_some() {
+                    hex += "</span>";
+                    ascii += "</span>";
+                }
+                if jj > 0 {
+                    hex += " ";
+                }
+                if region != cur_region {
+                    if let Some(region) = region {
+                        let mut label = String::new();
+                        escape_html(&mut label,
+                            &config.highlight[region].label).unwrap();
+                        let span = format!("<span class=\"hexdump-region\" \
+                            style=\"background-color:{}40\" title=\"{}\">",
+                            colors[region % colors.len()], label);
+                        hex += &span;
+                        ascii += &span;
+                    }
+                    cur_region = region;
+                }
+
+                hex += &format!("{:02x}", byte);
+                match byte {
+                    b'<' => ascii += "&lt;",
+                    b'>' => ascii += "&gt;",
+                    b'&' => ascii += "&amp;",
+                    0x20..=0x7e => ascii.push(byte as char),
+                    _ => ascii.push('.'),
+                }
+            }
+            if cur_region.is_some() {
+                hex += "</span>";
+                ascii += "</span>";
+            }
+
+            // Keep the ASCII lined up on a short last row
+            hex += &" ".repeat((config.width - row.len()) * 3);
+
+            rows += &row_template
+                .replace("<<<PUT THE OFFSET HERE>>>",
+                    &format!("{:0width$x}", offset, width = digits))
+                .replace("<<<PUT THE HEX HERE>>>", &hex)
+                .replace("<<<PUT THE ASCII HERE>>>", &ascii);
+        }
+
+        let mut legend = String::new();
+        let legend_template =
+            handler_template(website, ctx, "hexdump_legend.html").await?;
+        for (ii, region) in config.highlight.iter().enumerate() {
+            let mut label = String::new();
+            escape_html(&mut label, &region.label).unwrap();
+            legend += &legend_template
+                .replace("<<<PUT THE COLOR HERE>>>", &colors[ii % colors.len()])
+                .replace("<<<PUT THE LABEL HERE>>>", &label);
+        }
+
+        let (background, foreground) = theme_colors(website);
+        let output = handler_template(website, ctx, "hexdump.html").await?;
+        Ok(output.replace("<<<PUT THE BACKGROUND HERE>>>", &background)
+            .replace("<<<PUT THE FOREGROUND HERE>>>", &foreground)
+            .replace("<<<PUT THE ROWS HERE>>>", &rows)
+            .replace("<<<PUT THE LEGEND HERE>>>", &legend))
+    }
+}
+
+/// Disassembles x86 machine code into highlighted assembly, eg.
+///
+/// ```toml
+/// file = "blobs/boot.bin"
+/// length = 0x10
+/// arch = "x86-16"
+/// base = 0x7c00
+/// syntax = "intel"
+/// symbols = { entry = 0x7c00 }
+/// comments = [{ address = 0x7c00, text = "Interrupts off" }]
+/// ```
+#[derive(Default)]
+pub(crate) struct Disasm;
+
+#[derive(Debug, Deserialize)]
+struct DisasmConfig {
+    /// The machine code
+    #[serde(flatten)]
+    bytes: BytesConfig,
+
+    /// Architecture of the machine code
+    #[serde(default)]
+    arch: Arch,
+
+    /// Address of the first byte, defaults to `offset`
+    base: Option<u64>,
+
+    /// Assembly syntax to disassemble to
+    #[serde(default)]
+    syntax: AsmSyntax,
+
+    /// Names of addresses, used for labels and branch targets
+    #[serde(default)]
+    symbols: BTreeMap<String, u64>,
+
+    /// Comments on instructions
+    #[serde(default)]
+    comments: Vec<DisasmComment>,
+}
+
+/// Flavors of x86
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+enum Arch {
+    /// 16-bit real mode
+    #[serde(rename = "x86-16")]
+    X86_16,
+
+    /// 32-bit protected mode
+    #[serde(rename = "x86-32")]
+    X86_32,
+
+    /// 64-bit long mode
+    #[default]
+    #[serde(rename = "x86-64")]
+    X86_64,
+}
+
+/// Assembly syntaxes
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum AsmSyntax {
+    /// Intel syntax, eg. `mov eax, 1`
+    #[default]
+    Intel,
+
+    /// AT&T syntax, eg. `movl $1, %eax`
+    Att,
+}
+
+/// A comment on an instruction
+#[derive(Debug, Deserialize)]
+struct DisasmComment {
+    /// Address of the instruction
+    address: u64,
+
+    /// The comment
+    text: String,
+}
+
+/// Names addresses in operands after the block's symbols
+struct Symbols(BTreeMap<u64, String>);
+
+impl SymbolResolver for Symbols {
+    fn symbol(&mut self, _instruction: &Instruction, _operand: u32,
+            _instruction_operand: Option<u32>, address: u64,
+            _address_size: u32) -> Option<SymbolResult<'_>> {
+        self.0.get(&address).map(|x| SymbolResult::with_str(address, x))
+    }
+}
+
+#[async_trait]
+impl Handler for Disasm {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let config: DisasmConfig = parse_config(ctx, "disasm", input)?;
+        let (offset, bytes) =
+            load_bytes(website, ctx, "disasm", &config.bytes).await?;
+        let base = config.base.unwrap_or(offset as u64);
+
+        // The formatter and highlighter can't be held across awaits, so get
+        // the templates first
+        let output = handler_template(website, ctx, "disasm.html").await?;
+        let row_template =
+            handler_template(website, ctx, "disasm_row.html").await?;
+        let label_template =
+            handler_template(website, ctx, "disasm_label.html").await?;
+
+        let (bitness, digits) = match config.arch {
+            Arch::X86_16 => (16, 4),
+            Arch::X86_32 => (32, 8),
+            Arch::X86_64 => (64, 16),
+        };
+
+        // Label addresses with the symbols, and comment the instructions
+        let symbols = config.symbols.iter()
+            .map(|(name, &address)| (address, name.clone()))
+            .collect::<BTreeMap<_, _>>();
+        let mut comments = BTreeMap::new();
+        for comment in &config.comments {
+            comments.insert(comment.address, comment.text.as_str());
+        }
+        let mut formatter: Box<dyn Formatter> = match config.syntax {
+            AsmSyntax::Intel => Box::new(IntelFormatter::with_options(
+                Some(Box::new(Symbols(symbols.clone()))), None)),
+            AsmSyntax::Att => Box::new(GasFormatter::with_options(
+                Some(Box::new(Symbols(symbols.clone()))), None)),
+        };
+
+        // Highlight the instructions with the assembly syntax, and the
+        // comments like comments
+        let mut highlighter = website.syntax_set.find_syntax_by_token("asm")
+            .map(|x| HighlightLines::new(x, &website.theme));
+        let comment_color = css_color(Highlighter::new(&website.theme)
+            .style_for_stack(&[Scope::new("comment").unwrap()]).foreground);
+
+        // The IP wraps in 16-bit code, so the offset of each instruction into
+        // the bytes is counted rather than taken from its address
+        let mut rows = String::new();
+        let mut addresses = Vec::new();
+        let mut start = 0;
+        let mut decoder = Decoder::with_ip(bitness, &bytes, base,
+            DecoderOptions::NONE);
+        for instruction in &mut decoder {
+            let address = instruction.ip();
+            addresses.push(address);
+
+            if let Some(name) = symbols.get(&address) {
+                let mut escaped = String::new();
+                escape_html(&mut escaped, name).unwrap();
+                rows += &label_template
+                    .replace("<<<PUT THE SYMBOL HERE>>>", &escaped);
+            }
+
+            let mut text = String::new();
+            formatter.format(&instruction, &mut text);
+            let text = match &mut highlighter {
+                Some(highlighter) => {
+                    let line = text + "\n";
+                    let regions = highlighter.highlight(&line,
+                        &website.syntax_set);
+                    styled_line_to_highlighted_html(&regions,
+                        IncludeBackground::No).replace('\n', "")
+                }
+                None => {
+                    let mut escaped = String::new();
+                    escape_html(&mut escaped, &text).unwrap();
+                    escaped
+                }
+            };
+
+            let code = bytes[start..start + instruction.len()].iter()
+                .map(|x| format!("{:02x}", x)).collect::<Vec<_>>().join(" ");
+            start += instruction.len();
+
+            let comment = match comments.get(&address) {
+                Some(comment) => {
+                    let mut escaped = String::new();
+                    escape_html(&mut escaped, comment).unwrap();
+                    format!("<span style=\"color:{}\">; {}</span>",
+                        comment_color, escaped)
+                }
+                None => String::new(),
+            };
+
+            rows += &row_template
+                .replace("<<<PUT THE ADDRESS HERE>>>",
+                    &format!("{:0width$x}", address, width = digits))
+                .replace("<<<PUT THE BYTES HERE>>>", &code)
+                .replace("<<<PUT THE INSTRUCTION HERE>>>", &text)
+                .replace("<<<PUT THE COMMENT HERE>>>", &comment);
+        }
+
+        // Point out symbols and comments which didn't end up anywhere
+        for (address, name) in &symbols {
+            if !addresses.contains(address) {
+                ctx.warn(format!("symbol `{}` at {:#x} is not the start of \
+                    an instruction", name, address));
+            }
+        }
+        for address in comments.keys() {
+            if !addresses.contains(address) {
+                ctx.warn(format!("comment at {:#x} is not the start of an \
+                    instruction", address));
+            }
+        }
+
+        let (background, foreground) = theme_colors(website);
+        Ok(output.replace("<<<PUT THE BACKGROUND HERE>>>", &background)
+            .replace("<<<PUT THE FOREGROUND HERE>>>", &foreground)
+            .replace("<<<PUT THE ROWS HERE>>>", &rows))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_hex;
+
+    #[test]
+    fn hex_ignores_whitespace() {
+        assert_eq!(parse_hex("4d5a 90\n00").unwrap(), [0x4d, 0x5a, 0x90, 0]);
+        assert_eq!(parse_hex("DEADbeef").unwrap(), [0xde, 0xad, 0xbe, 0xef]);
+        assert!(parse_hex("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn hex_rejects_odd_digits() {
+        assert!(parse_hex("4d5a9").is_err());
+        assert!(parse_hex("4d 5a 9").is_err());
+    }
+
+    #[test]
+    fn hex_rejects_non_digits() {
+        assert!(parse_hex("+f").is_err());
+        assert!(parse_hex("0x4d").is_err());
+        assert!(parse_hex("4g").is_err());
+    }
+}
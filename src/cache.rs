@@ -0,0 +1,216 @@
+//! Caching of handler output across builds
+//!
+//! Each block is looked up by a hash of the handler name, the block text,
+//! where the block is (handlers are given the page's metadata, so it matters)
+//! and the website's settings: the config, which handlers read paths,
+//! permalinks and link decorations from, and the syntax theme and syntaxes,
+//! whose highlighting handlers bake into their output.
+//! The entry records the hashes of the files the handler declared it depends
+//! on, and is only used if none of them changed.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use serde_derive::{Serialize, Deserialize};
+use syntect::highlighting::Theme;
+use syntect::parsing::SyntaxSet;
+use crate::{Error, Result, Config, HandlerContext};
+
+/// Bumped whenever the format of entries changes
+const CACHE_VERSION: u32 = 1;
+
+/// A cached block
+#[derive(Serialize, Deserialize)]
+pub(crate) struct CacheEntry {
+    /// Files the output depends on, and their hashes
+    dependencies: Vec<(PathBuf, String)>,
+
+    /// What the handler returned
+    output: String,
+
+    /// Whether `output` is markdown
+    markdown: bool,
+
+    /// Warnings emitted by the handler
+    warnings: Vec<String>,
+
+    /// Files which need to be copied to the output directory
+    assets: Vec<PathBuf>,
+
+    /// Markdown files which need to be generated into pages
+    pages: Vec<PathBuf>,
+
+    /// Extra files to write, relative to `config.output_path`
+    outputs: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl CacheEntry {
+    /// Report everything the handler reported into `ctx`, returning the
+    /// handler's output
+    pub(crate) fn replay(self, ctx: &mut HandlerContext) -> String {
+        for (dependency, _) in self.dependencies {
+            ctx.add_dependency(dependency);
+        }
+        ctx.warnings = self.warnings;
+        ctx.assets = self.assets;
+        ctx.pages = self.pages;
+        ctx.outputs = self.outputs;
+        ctx.markdown = self.markdown;
+        self.output
+    }
+}
+
+/// Cache of handler output, stored as a JSON file per block in a directory
+pub(crate) struct HandlerCache {
+    /// Directory containing the entries
+    path: PathBuf,
+
+    /// Hash of the website's settings which handler output depends on
+    settings: String,
+
+    /// Number of blocks which were served from the cache
+    hits: AtomicUsize,
+}
+
+impl HandlerCache {
+    /// Use the cache in the directory `path` for a website with `config`,
+    /// highlighting with `theme` and `syntax_set`
+    pub(crate) fn new(path: impl Into<PathBuf>, config: &Config,
+            theme: &Theme, syntax_set: &SyntaxSet) -> Self {
+        // The theme and syntaxes may not come from the config, so they're
+        // hashed themselves
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(format!("{:?}", config).as_bytes());
+        serde_json::to_writer(&mut hasher, theme).unwrap();
+        serde_json::to_writer(&mut hasher, syntax_set).unwrap();
+
+        HandlerCache {
+            path:     path.into(),
+            settings: hasher.finalize().to_hex().to_string(),
+            hits:     AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of blocks which were served from the cache so far
+    pub(crate) fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Get the key for the block `input` of the handler `name` described by
+    /// `ctx`
+    pub(crate) fn key(&self, name: &str, input: &str, ctx: &HandlerContext)
+            -> String {
+        let key = serde_json::to_string(&(CACHE_VERSION,
+            env!("CARGO_PKG_VERSION"), name, input, &self.settings,
+            ctx.source, ctx.page_url, ctx.page, ctx.inline)).unwrap();
+        blake3::hash(key.as_bytes()).to_hex().to_string()
+    }
+
+    /// Look up the entry for `key`, if there is one and none of its
+    /// dependencies changed
+    pub(crate) async fn get(&self, key: &str) -> Option<CacheEntry> {
+        let entry = tokio::fs::read(self.path.join(key)).await.ok()?;
+        let entry: CacheEntry = serde_json::from_slice(&entry).ok()?;
+
+        for (dependency, hash) in &entry.dependencies {
+            if hash_path(dependency).await != *hash {
+                return None;
+            }
+        }
+
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(entry)
+    }
+
+    /// Store what the handler reported to `ctx` and its `output` for `key`
+    pub(crate) async fn put(&self, key: &str, ctx: &HandlerContext<'_>,
+            output: &str) -> Result<()> {
+        let mut dependencies = Vec::new();
+        for dependency in &ctx.dependencies {
+            dependencies.push(
+                (dependency.clone(), hash_path(dependency).await));
+        }
+
+        let entry = serde_json::to_vec(&CacheEntry {
+            output:   output.to_string(),
+            markdown: ctx.markdown,
+            warnings: ctx.warnings.clone(),
+            assets:   ctx.assets.clone(),
+            pages:    ctx.pages.clone(),
+            outputs:  ctx.outputs.clone(),
+            dependencies,
+        }).unwrap();
+
+        tokio::fs::create_dir_all(&self.path).await
+            .map_err(|x| Error::WriteCache(self.path.clone(), x))?;
+        let path = self.path.join(key);
+        tokio::fs::write(&path, entry).await
+            .map_err(|x| Error::WriteCache(path, x))
+    }
+}
+
+/// Hash the contents of the file at `path`. Directories are hashed by the
+/// paths of everything in them, including their subdirectories, and files
+/// which don't exist all have the same hash
+async fn hash_path(path: &Path) -> String {
+    let mut hasher = blake3::Hasher::new();
+
+    if tokio::fs::metadata(path).await.is_ok_and(|x| x.is_dir()) {
+        // Walk the tree without following links, which could loop
+        let mut names = Vec::new();
+        let mut dirs = vec![PathBuf::new()];
+        while let Some(dir) = dirs.pop() {
+            let Ok(mut entries) = tokio::fs::read_dir(path.join(&dir)).await
+                else { continue };
+            while let Ok(Some(dirent)) = entries.next_entry().await {
+                let name = dir.join(dirent.file_name());
+                if dirent.file_type().await.is_ok_and(|x| x.is_dir()) {
+                    dirs.push(name.clone());
+                }
+                names.push(name);
+            }
+        }
+        names.sort();
+
+        hasher.update(b"dir");
+        for name in names {
+            hasher.update(name.to_string_lossy().as_bytes());
+            hasher.update(b"\0");
+        }
+    } else if let Ok(contents) = tokio::fs::read(path).await {
+        hasher.update(b"file");
+        hasher.update(&contents);
+    } else {
+        hasher.update(b"missing");
+    }
+
+    hasher.finalize().to_hex().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::hash_path;
+
+    #[tokio::test]
+    async fn directories_hash_their_tree() {
+        let dir = std::env::temp_dir()
+            .join(format!("cache-hash-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("blog")).unwrap();
+        let empty = hash_path(&dir).await;
+
+        // New files in subdirectories change the hash, but not their
+        // contents
+        std::fs::write(dir.join("blog/x.md"), "a").unwrap();
+        let nested = hash_path(&dir).await;
+        assert_ne!(empty, nested);
+        std::fs::write(dir.join("blog/x.md"), "b").unwrap();
+        assert_eq!(hash_path(&dir).await, nested);
+
+        // Moving a file is a change too
+        std::fs::rename(dir.join("blog/x.md"), dir.join("x.md")).unwrap();
+        assert_ne!(hash_path(&dir).await, nested);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(hash_path(&dir).await,
+            hash_path(&dir.join("missing")).await);
+    }
+}
@@ -0,0 +1,599 @@
+//! Charts rendered into SVG at build time
+
+use std::sync::Arc;
+use std::path::PathBuf;
+use std::fmt::Write;
+use async_trait::async_trait;
+use serde_derive::Deserialize;
+use pulldown_cmark::escape::escape_html;
+use crate::{Error, Result, Website, Handler, HandlerContext};
+use crate::handlers::{handler_template, parse_config, load_csv};
+use crate::handlers::{theme_colors, theme_palette};
+
+/// Space around the plot for the title, ticks, and axis labels, in pixels
+const MARGIN_TOP: f64 = 36.0;
+const MARGIN_RIGHT: f64 = 20.0;
+const MARGIN_BOTTOM: f64 = 48.0;
+const MARGIN_LEFT: f64 = 64.0;
+
+/// Height of a row of the legend, in pixels
+const LEGEND_ROW: f64 = 20.0;
+
+/// Rough width of a character of text, for laying out the legend
+const CHAR_WIDTH: f64 = 7.0;
+
+/// Renders data into a line, bar, or scatter chart, eg.
+///
+/// ```toml
+/// kind = "line"
+/// title = "Coverage over time"
+/// x_label = "Hours"
+/// y_label = "Edges"
+/// y_log = true
+/// file = "data/coverage.csv"
+/// ```
+///
+/// The first column of a CSV file is the x values, and every other column is
+/// a series named by its header. Data can also be given inline:
+///
+/// ```toml
+/// x = [1, 2, 4, 8]
+/// series = [
+///     { name = "Mutex", y = [1.0, 1.9, 3.2, 4.1] },
+///     { name = "Lock-free", y = [1.0, 2.0, 3.9, 7.7] },
+/// ]
+/// ```
+///
+/// x values may be strings, which puts them evenly along the axis
+#[derive(Default)]
+pub(crate) struct Chart;
+
+#[derive(Debug, Deserialize)]
+struct ChartConfig {
+    /// Kind of chart to draw
+    #[serde(default)]
+    kind: ChartKind,
+
+    /// Title drawn above the chart
+    title: Option<String>,
+
+    /// Label of the x axis
+    x_label: Option<String>,
+
+    /// Label of the y axis
+    y_label: Option<String>,
+
+    /// Use a logarithmic x axis
+    #[serde(default)]
+    x_log: bool,
+
+    /// Use a logarithmic y axis
+    #[serde(default)]
+    y_log: bool,
+
+    /// Width of the chart, in pixels
+    #[serde(default = "default_width")]
+    width: f64,
+
+    /// Height of the chart, in pixels
+    #[serde(default = "default_height")]
+    height: f64,
+
+    /// CSV file with the data, relative to `config.content_path`
+    file: Option<PathBuf>,
+
+    /// The x values of inline data
+    x: Option<Vec<toml::Value>>,
+
+    /// Series of inline data
+    #[serde(default)]
+    series: Vec<SeriesConfig>,
+}
+
+/// Default width of a chart
+fn default_width() -> f64 {
+    640.0
+}
+
+/// Default height of a chart
+fn default_height() -> f64 {
+    400.0
+}
+
+/// Kinds of chart
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ChartKind {
+    /// Points joined by lines
+    #[default]
+    Line,
+
+    /// Bars for each x value, side by side for each series
+    Bar,
+
+    /// Points
+    Scatter,
+}
+
+/// A series of inline data
+#[derive(Debug, Deserialize)]
+struct SeriesConfig {
+    /// Name of the series in the legend
+    #[serde(default)]
+    name: String,
+
+    /// The y values, one for each x value
+    y: Vec<f64>,
+}
+
+/// A series to draw
+struct Series {
+    /// Name of the series in the legend
+    name: String,
+
+    /// The y value for each x value, if it has one
+    y: Vec<Option<f64>>,
+}
+
+/// How values map onto an axis
+struct Scale {
+    /// Smallest value on the axis
+    lo: f64,
+
+    /// Largest value on the axis
+    hi: f64,
+
+    /// Whether the axis is logarithmic
+    log: bool,
+
+    /// Values to put ticks at
+    ticks: Vec<f64>,
+
+    /// Decimal places to label ticks with
+    decimals: usize,
+}
+
+impl Scale {
+    /// Create a scale covering `min` to `max`, which must be finite, and
+    /// positive for a `log` scale
+    fn new(min: f64, max: f64, log: bool) -> Self {
+        if log {
+            let lo = min.log10().floor() as i32;
+            let hi = (max.log10().ceil() as i32).max(lo + 1);
+            return Scale {
+                lo: 10f64.powi(lo),
+                hi: 10f64.powi(hi),
+                log,
+                ticks: (lo..=hi).map(|x| 10f64.powi(x)).collect(),
+                decimals: (-lo).max(0) as usize,
+            };
+        }
+
+        // A single value gets some room around it, relative to its size so
+        // the range isn't lost to rounding
+        let (min, max) = if min == max {
+            let pad = (min.abs() / 10.0).max(1.0);
+            (min - pad, max + pad)
+        } else {
+            (min, max)
+        };
+
+        // Pick a step of 1, 2, or 5 times a power of 10 giving about 5 ticks
+        let raw = (max - min) / 5.0;
+        let magnitude = 10f64.powf(raw.log10().floor());
+        let step = magnitude * match raw / magnitude {
+            x if x < 1.5 => 1.0,
+            x if x < 3.0 => 2.0,
+            x if x < 7.0 => 5.0,
+            _ => 10.0,
+        };
+
+        let lo = (min / step).floor() * step;
+        let hi = (max / step).ceil() * step;
+        let count = ((hi - lo) / step).round() as usize;
+        Scale {
+            lo, hi, log,
+            ticks: (0..=count).map(|x| lo + x as f64 * step).collect(),
+            decimals: (-step.log10().floor()).max(0.0) as usize,
+        }
+    }
+
+    /// Get where `value` is along the axis, from 0 to 1
+    fn position(&self, value: f64) -> f64 {
+        if self.log {
+            (value.log10() - self.lo.log10()) /
+                (self.hi.log10() - self.lo.log10())
+        } else {
+            (value - self.lo) / (self.hi - self.lo)
+        }
+    }
+
+    /// Format the tick at `value`
+    fn label(&self, value: f64) -> String {
+        if self.log && !(1e-4..=1e5).contains(&value) {
+            format!("1e{}", value.log10().round())
+        } else {
+            format!("{:.*}", self.decimals, value)
+        }
+    }
+}
+
+/// The x axis of a chart
+enum XAxis {
+    /// Values placed along a scale
+    Numeric(Vec<f64>, Scale),
+
+    /// Categories placed evenly along the axis
+    Categories(Vec<String>),
+}
+
+impl XAxis {
+    /// Get where the `idx`th x value is along the axis, from 0 to 1
+    fn position(&self, idx: usize) -> f64 {
+        match self {
+            XAxis::Numeric(values, scale) => scale.position(values[idx]),
+            XAxis::Categories(names) =>
+                (idx as f64 + 0.5) / names.len() as f64,
+        }
+    }
+}
+
+/// Escape `text` for use in SVG
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    escape_html(&mut escaped, text).unwrap();
+    escaped
+}
+
+/// Load the x values and series of the chart from a CSV file or inline data
+async fn load_data(website: &Website, ctx: &mut HandlerContext<'_>,
+        config: &ChartConfig) -> Result<(Vec<String>, Vec<Series>)> {
+    let bad_input = |ctx: &HandlerContext, message: String|
+        Error::BadHandlerInput(ctx.source.to_path_buf(), "chart".into(),
+            message);
+
+    match (&config.file, &config.x) {
+        (Some(file), None) => {
+            let (header, rows) = load_csv(website, ctx, "chart", file, None)
+                .await?;
+            let mut x = Vec::new();
+            let mut series = header.into_iter().skip(1).map(|name| Series {
+                name,
+                y: Vec::new(),
+            }).collect::<Vec<_>>();
+
+            for row in rows {
+                x.push(row[0].clone());
+                for (ii, series) in series.iter_mut().enumerate() {
+                    // Empty cells leave a gap
+                    let cell = row[ii + 1].trim();
+                    series.y.push(if cell.is_empty() { None } else {
+                        Some(cell.parse().map_err(|_| bad_input(ctx,
+                            format!("`{}` is not a number", cell)))?)
+                    });
+                }
+            }
+            Ok((x, series))
+        }
+        (None, Some(x)) => {
+            let x = x.iter().map(|x| match x {
+                toml::Value::String(x) => x.clone(),
+                x => x.to_string(),
+            }).collect::<Vec<_>>();
+            let series = config.series.iter().map(|series| Series {
+                name: series.name.clone(),
+                y:    series.y.iter().map(|&y| Some(y)).collect(),
+            }).collect();
+            Ok((x, series))
+        }
+        _ => Err(bad_input(ctx,
+            "exactly one of `file` and `x` is needed".into())),
+    }
+}
+
+/// Draw the chart described by `config` with `x` values and `series` into
+/// SVG. `colors` are the theme's background, foreground, and series colors
+fn draw(config: &ChartConfig, x: Vec<String>, series: &[Series],
+        (background, foreground, palette): (&str, &str, &[String]))
+        -> std::result::Result<String, String> {
+    // Numbers go along a scale, unless they're bars which are always evenly
+    // spaced
+    let numbers = x.iter().map(|x| x.trim().parse::<f64>())
+        .collect::<std::result::Result<Vec<_>, _>>();
+    let x_axis = match numbers {
+        Ok(numbers) if config.kind != ChartKind::Bar && !numbers.is_empty() &&
+                numbers.iter().all(|x| x.is_finite()) => {
+            if config.x_log && numbers.iter().any(|&x| x <= 0.0) {
+                return Err("`x_log` needs positive x values".into());
+            }
+            let min = numbers.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = numbers.iter().copied()
+                .fold(f64::NEG_INFINITY, f64::max);
+            let scale = Scale::new(min, max, config.x_log);
+            XAxis::Numeric(numbers, scale)
+        }
+        _ if config.x_log => {
+            return Err("`x_log` needs numeric x values and not bars".into());
+        }
+        _ => XAxis::Categories(x.clone()),
+    };
+
+    // Find the range of y values, bars start from 0
+    let values = series.iter().flat_map(|x| x.y.iter().flatten().copied())
+        .collect::<Vec<_>>();
+    if values.is_empty() {
+        return Err("there is no data".into());
+    }
+    if let Some(y) = values.iter().find(|y| !y.is_finite()) {
+        return Err(format!("`{}` is not a finite number", y));
+    }
+    if config.y_log && values.iter().any(|&y| y <= 0.0) {
+        return Err("`y_log` needs positive y values".into());
+    }
+    let mut min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let mut max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    if config.kind == ChartKind::Bar && !config.y_log {
+        min = min.min(0.0);
+        max = max.max(0.0);
+    }
+    let y_scale = Scale::new(min, max, config.y_log);
+
+    // Lay out the legend in rows under the chart
+    let named = series.iter().any(|x| !x.name.is_empty());
+    let mut legend = Vec::new();
+    let (mut legend_x, mut legend_rows) = (MARGIN_LEFT, 0);
+    if named {
+        legend_rows = 1;
+        for series in series {
+            let width = 24.0 + series.name.chars().count() as f64 * CHAR_WIDTH;
+            if legend_x + width > config.width && legend_x > MARGIN_LEFT {
+                legend_x = MARGIN_LEFT;
+                legend_rows += 1;
+            }
+            legend.push((legend_x, legend_rows - 1));
+            legend_x += width;
+        }
+    }
+
+    // Where the plot goes
+    let left = MARGIN_LEFT;
+    let top = MARGIN_TOP;
+    let right = config.width - MARGIN_RIGHT;
+    let bottom = config.height - MARGIN_BOTTOM -
+        legend_rows as f64 * LEGEND_ROW;
+    if right <= left || bottom <= top {
+        return Err("the chart is too small".into());
+    }
+    let px = |pos: f64| left + pos * (right - left);
+    let py = |pos: f64| bottom - pos * (bottom - top);
+
+    let mut svg = String::new();
+    let (width, height) = (config.width, config.height);
+    writeln!(svg, "<svg class=\"chart\" xmlns=\"http://www.w3.org/2000/svg\" \
+        width=\"{width}\" height=\"{height}\" \
+        viewBox=\"0 0 {width} {height}\" role=\"img\" \
+        font-family=\"sans-serif\" font-size=\"12\" fill=\"{foreground}\">")
+        .unwrap();
+    if let Some(title) = &config.title {
+        writeln!(svg, "<title>{}</title>", escape(title)).unwrap();
+    }
+    writeln!(svg, "<rect width=\"100%\" height=\"100%\" \
+        fill=\"{background}\"/>").unwrap();
+    if let Some(title) = &config.title {
+        writeln!(svg, "<text x=\"{}\" y=\"22\" text-anchor=\"middle\" \
+            font-size=\"14\" font-weight=\"bold\">{}</text>",
+            width / 2.0, escape(title)).unwrap();
+    }
+
+    // Grid lines and ticks
+    for &tick in &y_scale.ticks {
+        let y = py(y_scale.position(tick));
+        writeln!(svg, "<line x1=\"{left}\" y1=\"{y:.1}\" x2=\"{right}\" \
+            y2=\"{y:.1}\" stroke=\"{foreground}\" stroke-opacity=\"0.15\"/>\n\
+            <text x=\"{}\" y=\"{y:.1}\" text-anchor=\"end\" \
+            dominant-baseline=\"middle\">{}</text>",
+            left - 6.0, y_scale.label(tick)).unwrap();
+    }
+    match &x_axis {
+        XAxis::Numeric(_, scale) => {
+            for &tick in &scale.ticks {
+                let x = px(scale.position(tick));
+                writeln!(svg, "<line x1=\"{x:.1}\" y1=\"{top}\" x2=\"{x:.1}\" \
+                    y2=\"{bottom}\" stroke=\"{foreground}\" \
+                    stroke-opacity=\"0.15\"/>\n\
+                    <text x=\"{x:.1}\" y=\"{}\" \
+                    text-anchor=\"middle\">{}</text>",
+                    bottom + 16.0, scale.label(tick)).unwrap();
+            }
+        }
+        XAxis::Categories(names) => {
+            for (ii, name) in names.iter().enumerate() {
+                writeln!(svg, "<text x=\"{:.1}\" y=\"{}\" \
+                    text-anchor=\"middle\">{}</text>",
+                    px(x_axis.position(ii)), bottom + 16.0, escape(name))
+                    .unwrap();
+            }
+        }
+    }
+
+    // Axes and their labels
+    writeln!(svg, "<path d=\"M{left} {top}V{bottom}H{right}\" fill=\"none\" \
+        stroke=\"{foreground}\"/>").unwrap();
+    if let Some(label) = &config.x_label {
+        writeln!(svg, "<text x=\"{:.1}\" y=\"{}\" \
+            text-anchor=\"middle\">{}</text>",
+            (left + right) / 2.0, bottom + 36.0, escape(label)).unwrap();
+    }
+    if let Some(label) = &config.y_label {
+        let y = (top + bottom) / 2.0;
+        writeln!(svg, "<text x=\"14\" y=\"{y:.1}\" text-anchor=\"middle\" \
+            transform=\"rotate(-90 14 {y:.1})\">{}</text>", escape(label))
+            .unwrap();
+    }
+
+    // The data
+    let base = py(y_scale.position(if y_scale.log { y_scale.lo }
+        else { 0f64.clamp(y_scale.lo, y_scale.hi) }));
+    let count = series.len();
+    for (ii, series) in series.iter().enumerate() {
+        let color = &palette[ii % palette.len()];
+        let points = series.y.iter().enumerate().take(x.len())
+            .map(|(jj, y)| y.map(|y|
+                (jj, px(x_axis.position(jj)), py(y_scale.position(y)))))
+            .collect::<Vec<_>>();
+
+        match config.kind {
+            ChartKind::Line => {
+                // Missing values break the line, leaving points on their own
+                // which are drawn as dots
+                for run in points.split(|x| x.is_none()) {
+                    if let [Some((_, x, y))] = run {
+                        writeln!(svg, "<circle cx=\"{x:.1}\" cy=\"{y:.1}\" \
+                            r=\"2\" fill=\"{color}\"/>").unwrap();
+                        continue;
+                    }
+
+                    let path = run.iter().flatten().enumerate()
+                        .map(|(kk, (_, x, y))| format!("{}{:.1} {:.1}",
+                            if kk == 0 { "M" } else { "L" }, x, y))
+                        .collect::<String>();
+                    if !path.is_empty() {
+                        writeln!(svg, "<path d=\"{path}\" fill=\"none\" \
+                            stroke=\"{color}\" stroke-width=\"2\"/>")
+                            .unwrap();
+                    }
+                }
+            }
+            ChartKind::Scatter => {
+                for (_, x, y) in points.iter().flatten() {
+                    writeln!(svg, "<circle cx=\"{x:.1}\" cy=\"{y:.1}\" r=\"3\" \
+                        fill=\"{color}\"/>").unwrap();
+                }
+            }
+            ChartKind::Bar => {
+                // Bars for each x value share 80% of its space
+                let band = (right - left) / x.len() as f64;
+                let bar = band * 0.8 / count as f64;
+                for (jj, _, y) in points.iter().flatten() {
+                    let x = left + band * (*jj as f64 + 0.1) + bar * ii as f64;
+                    writeln!(svg, "<rect x=\"{x:.1}\" y=\"{:.1}\" \
+                        width=\"{bar:.1}\" height=\"{:.1}\" \
+                        fill=\"{color}\"/>",
+                        y.min(base), (y - base).abs()).unwrap();
+                }
+            }
+        }
+    }
+
+    // The legend
+    for (ii, (series, (x, row))) in series.iter().zip(legend).enumerate() {
+        let y = bottom + MARGIN_BOTTOM + (row as f64 + 0.5) * LEGEND_ROW;
+        writeln!(svg, "<rect x=\"{x:.1}\" y=\"{:.1}\" width=\"12\" \
+            height=\"12\" fill=\"{}\"/>\n\
+            <text x=\"{:.1}\" y=\"{y:.1}\" \
+            dominant-baseline=\"middle\">{}</text>",
+            y - 6.0, palette[ii % palette.len()], x + 16.0,
+            escape(&series.name)).unwrap();
+    }
+
+    svg += "</svg>";
+    Ok(svg)
+}
+
+#[async_trait]
+impl Handler for Chart {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let config: ChartConfig = parse_config(ctx, "chart", input)?;
+        let (x, series) = load_data(website, ctx, &config).await?;
+
+        let (background, foreground) = theme_colors(website);
+        let svg = draw(&config, x, &series,
+                (&background, &foreground, &theme_palette(website)))
+            .map_err(|x| Error::BadHandlerInput(ctx.source.to_path_buf(),
+                "chart".into(), x))?;
+
+        let output = handler_template(website, ctx, "chart.html").await?;
+        Ok(output.replace("<<<PUT THE CHART HERE>>>", &svg))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChartConfig, Scale, Series, draw};
+
+    /// Check `scale` is usable: it covers some range, and its ticks are in
+    /// order from `lo` to `hi`
+    fn check(scale: &Scale) {
+        assert!(scale.lo.is_finite() && scale.hi.is_finite());
+        assert!(scale.lo < scale.hi);
+        let close = |a: f64, b: f64|
+            (a - b).abs() <= (scale.hi - scale.lo) / 1e9;
+        assert!(close(scale.ticks[0], scale.lo));
+        assert!(close(*scale.ticks.last().unwrap(), scale.hi));
+        assert!(scale.ticks.windows(2).all(|x| x[0] < x[1]));
+    }
+
+    #[test]
+    fn round_steps() {
+        let scale = Scale::new(0.0, 97.0, false);
+        check(&scale);
+        assert_eq!(scale.ticks, [0.0, 20.0, 40.0, 60.0, 80.0, 100.0]);
+        assert_eq!(scale.label(40.0), "40");
+        assert_eq!(scale.position(50.0), 0.5);
+
+        let scale = Scale::new(0.02, 0.09, false);
+        check(&scale);
+        assert_eq!(scale.decimals, 2);
+    }
+
+    #[test]
+    fn equal_values() {
+        for value in [0.0, 5.0, -3.0, 1e20, -1e-20] {
+            let scale = Scale::new(value, value, false);
+            check(&scale);
+            let position = scale.position(value);
+            assert!(position > 0.0 && position < 1.0, "{}", value);
+        }
+        assert_eq!(Scale::new(5.0, 5.0, false).ticks,
+            [4.0, 4.5, 5.0, 5.5, 6.0]);
+    }
+
+    #[test]
+    fn log_scales() {
+        let scale = Scale::new(3.0, 2000.0, true);
+        check(&scale);
+        assert_eq!(scale.ticks, [1.0, 10.0, 100.0, 1000.0, 10000.0]);
+        assert_eq!(scale.position(100.0), 0.5);
+
+        // A single value still spans a decade
+        for value in [1.0, 100.0, 0.5] {
+            let scale = Scale::new(value, value, true);
+            check(&scale);
+            assert!(scale.position(value).is_finite());
+        }
+        assert_eq!(Scale::new(1e-6, 1e6, true).label(1e6), "1e6");
+    }
+
+    /// Draw a line chart of `y` at x values 1, 2, ...
+    fn draw_line(y: &[Option<f64>]) -> Result<String, String> {
+        let config: ChartConfig = toml::from_str("").unwrap();
+        let x = (1..=y.len()).map(|x| x.to_string()).collect();
+        let series = [Series { name: String::new(), y: y.to_vec() }];
+        draw(&config, x, &series, ("#fff", "#000", &["#f00".to_string()]))
+    }
+
+    #[test]
+    fn draws_little_data() {
+        assert_eq!(draw_line(&[]).unwrap_err(), "there is no data");
+        assert_eq!(draw_line(&[None, None]).unwrap_err(), "there is no data");
+        assert_eq!(draw_line(&[Some(1.0), Some(f64::NAN)]).unwrap_err(),
+            "`NaN` is not a finite number");
+
+        for y in [&[Some(7.0)][..], &[Some(2.0), Some(2.0)],
+                &[None, Some(1.0)]] {
+            let svg = draw_line(y).unwrap();
+            assert!(!svg.contains("NaN") && !svg.contains("inf"), "{}", svg);
+        }
+    }
+}
@@ -0,0 +1,417 @@
+//! Named collections of pages, eg. a blog or a list of talks
+//!
+//! Collections are declared in the config, eg.
+//!
+//! ```toml
+//! [collections.blog]
+//! path = "blog"
+//! permalink = "/blog/{year}/{slug}/"
+//! template = "post.html"
+//! style = "post.css"
+//!
+//! [collections.blog.feed]
+//! url = "https://gamozolabs.com"
+//! title = "Gamozo Labs Blog"
+//! author = "Brandon Falk"
+//! ```
+//!
+//! Every markdown file in the collection's directory is a page in it, and
+//! pages which don't give a `style` or `template` in their `templateinfo`
+//! use the collection's. Pages are picked out with a [`CollectionQuery`]
+//! from:
+//!
+//! - handlers, with [`crate::Website::collection`]
+//! - markdown, with the `index` handler's `collection`
+//! - page templates, with `<<<PUT COLLECTION <name> [args] HERE>>>`, where
+//!   the args are a limit, `upcoming` or `past`, and `reverse`
+//! - scripts, with `collection(name)` or `collection(name, query)`
+
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Local};
+use serde_derive::Deserialize;
+use pulldown_cmark::escape::escape_html;
+use crate::{Error, Result, Config, Website, HandlerContext, TemplateInfo};
+use crate::assets::normalize_path;
+use crate::handlers::{handler_template, list_posts, render_posts};
+use crate::shortcodes::split_args;
+
+/// A named collection of pages
+#[derive(Debug, Deserialize)]
+pub struct CollectionConfig {
+    /// Directory of the pages, relative to `config.content_path`
+    pub path: PathBuf,
+
+    /// Where the pages are generated, relative to `config.output_path`. The
+    /// placeholders `{dir}`, `{slug}`, `{year}`, `{month}`, and `{day}` are
+    /// filled in for each page, where the slug is `templateinfo.slug` or the
+    /// file name of the markdown. Patterns ending in `/` generate
+    /// `index.html` in the directory, so URLs don't have an extension, eg.
+    /// `/blog/{year}/{slug}/`. Defaults to `{dir}/{slug}.html`
+    pub permalink: Option<String>,
+
+    /// Template for pages which don't give one, relative to
+    /// `config.content_path`
+    pub template: Option<PathBuf>,
+
+    /// Stylesheet for pages which don't give one, relative to
+    /// `config.content_path`
+    pub style: Option<PathBuf>,
+
+    /// What to sort the pages by
+    #[serde(default)]
+    pub sort: SortKey,
+
+    /// Sort the pages from largest to smallest, eg. newest first. Defaults
+    /// to `true`
+    #[serde(default = "default_descending")]
+    pub descending: bool,
+
+    /// Atom feed of the collection, if it has one
+    pub feed: Option<FeedConfig>,
+}
+
+/// Default order of collections
+fn default_descending() -> bool {
+    true
+}
+
+/// What the pages of a collection are sorted by
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortKey {
+    /// `templateinfo.time`
+    #[default]
+    Time,
+
+    /// `templateinfo.title`
+    Title,
+}
+
+/// An Atom feed of the newest pages of a collection
+#[derive(Debug, Deserialize)]
+pub struct FeedConfig {
+    /// Where to write the feed, relative to `config.output_path`. Defaults
+    /// to `feed.xml` in the collection's directory
+    pub path: Option<PathBuf>,
+
+    /// URL the website is hosted at, as feeds need absolute links, eg.
+    /// `https://gamozolabs.com`
+    pub url: String,
+
+    /// Title of the feed
+    pub title: String,
+
+    /// Author of the pages
+    pub author: String,
+
+    /// Most pages to put in the feed. Defaults to 20
+    #[serde(default = "default_feed_limit")]
+    pub limit: usize,
+}
+
+/// Default number of pages in a feed
+fn default_feed_limit() -> usize {
+    20
+}
+
+/// Which pages of a collection to get, eg. in an `index` block
+///
+/// ```toml
+/// collection = "talks"
+/// when = "upcoming"
+/// reverse = true
+/// limit = 5
+/// ```
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct CollectionQuery {
+    /// Most pages to get
+    pub limit: Option<usize>,
+
+    /// Only get pages whose `templateinfo.time` is in the future or past
+    pub when: Option<When>,
+
+    /// Get the pages in the opposite order to the collection's
+    #[serde(default)]
+    pub reverse: bool,
+}
+
+/// Pages from when, relative to the build
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum When {
+    /// Pages whose time hasn't happened yet
+    Upcoming,
+
+    /// Pages whose time has happened
+    Past,
+}
+
+/// Find the collection which the markdown at `path` is in, preferring the
+/// innermost if collections are nested
+pub(crate) fn containing<'a>(config: &'a Config, path: &Path)
+        -> Option<&'a CollectionConfig> {
+    let path = normalize_path(path);
+    config.collections.values().filter(|x| {
+        path.starts_with(normalize_path(config.content_path.join(&x.path)))
+    }).max_by_key(|x| normalize_path(&x.path).components().count())
+}
+
+/// Get the URL and metadata of the pages in the collection `name` picked by
+/// `query`, making sure they all get generated into pages
+pub(crate) async fn query(website: &Arc<Website>,
+        ctx: &mut HandlerContext<'_>, name: &str, query: &CollectionQuery)
+        -> Result<Vec<(String, TemplateInfo)>> {
+    let collection = website.config.collections.get(name).ok_or_else(||
+        Error::UnknownCollection(ctx.source.to_path_buf(), name.into()))?;
+    let pages = list_posts(website, ctx,
+        &website.config.content_path.join(&collection.path)).await?;
+    Ok(select(collection, query, Local::now(), pages))
+}
+
+/// Pick the `pages` of `collection` which `query` wants as of `now`, in the
+/// order it wants them
+fn select(collection: &CollectionConfig, query: &CollectionQuery,
+        now: DateTime<Local>, mut pages: Vec<(String, TemplateInfo)>)
+        -> Vec<(String, TemplateInfo)> {
+    // Only keep the pages from when the query wants
+    pages.retain(|(_, page)| match query.when {
+        Some(When::Upcoming) => page.time > now,
+        Some(When::Past) => page.time <= now,
+        None => true,
+    });
+
+    // Sort the pages, with the URL breaking ties so the order is stable
+    pages.sort_by(|(a_url, a), (b_url, b)| match collection.sort {
+        SortKey::Time => a.time.cmp(&b.time),
+        SortKey::Title => a.title.cmp(&b.title),
+    }.then_with(|| a_url.cmp(b_url)));
+    if collection.descending != query.reverse {
+        pages.reverse();
+    }
+
+    if let Some(limit) = query.limit {
+        pages.truncate(limit);
+    }
+    pages
+}
+
+/// Parse the `args` of `<<<PUT COLLECTION <args> HERE>>>` in the template of
+/// the markdown at `source` into the name of the collection and the query
+fn parse_query(source: &Path, args: &str)
+        -> Result<(String, CollectionQuery)> {
+    let mut args = split_args(args).into_iter();
+    let name = args.next().unwrap_or_default();
+
+    // The name comes first, then the query in any order
+    let mut query = CollectionQuery::default();
+    for arg in args {
+        match arg.as_str() {
+            "upcoming" => query.when = Some(When::Upcoming),
+            "past" => query.when = Some(When::Past),
+            "reverse" => query.reverse = true,
+            x => query.limit = Some(x.parse().map_err(|_|
+                Error::BadCollectionQuery(source.to_path_buf(), x.into()))?),
+        }
+    }
+    Ok((name, query))
+}
+
+/// Replace `<<<PUT COLLECTION <name> [args] HERE>>>` in the page `template`
+/// with the collection's pages, for the page described by `ctx`
+pub(crate) async fn fill_template(website: &Arc<Website>,
+        ctx: &mut HandlerContext<'_>, template: &str) -> Result<String> {
+    const OPEN: &str = "<<<PUT COLLECTION ";
+    const CLOSE: &str = " HERE>>>";
+
+    let mut filled = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(OPEN) {
+        let Some(end) = rest[start..].find(CLOSE) else { break };
+        let (name, collection) = parse_query(ctx.source,
+            &rest[start + OPEN.len()..start + end])?;
+        let pages = query(website, ctx, &name, &collection).await?;
+
+        filled += &rest[..start];
+        filled += &render_posts(website, ctx, &pages).await?;
+        rest = &rest[start + end + CLOSE.len()..];
+    }
+    filled += rest;
+
+    Ok(filled)
+}
+
+/// Escape `text` for use in XML
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    escape_html(&mut escaped, text).unwrap();
+    escaped
+}
+
+/// Fill in the feed's `entry` template for the page at `url` described by
+/// `page`, on the website hosted at `base`
+fn feed_entry(entry: &str, base: &str, url: &str, page: &TemplateInfo)
+        -> String {
+    let url = format!("{}{}", base, url);
+    entry.replace("<<<PUT THE URL HERE>>>", &escape(&url))
+        .replace("<<<PUT THE TITLE HERE>>>", &escape(&page.title))
+        .replace("<<<PUT THE DATE HERE>>>", &page.time.to_rfc3339())
+        .replace("<<<PUT THE DESCRIPTION HERE>>>",
+            &escape(&page.description))
+}
+
+/// Write the Atom feeds of the collections which have one
+pub(crate) async fn write_feeds(website: &Arc<Website>) -> Result<()> {
+    for (name, collection) in &website.config.collections {
+        let Some(feed) = &collection.feed else { continue };
+        let path = feed.path.clone()
+            .unwrap_or_else(|| collection.path.join("feed.xml"));
+        let base = feed.url.trim_end_matches('/');
+        let feed_url = format!("{}/{}", base,
+            crate::links::url_path(&path));
+
+        // The feed is generated on behalf of the collection's directory
+        let source = website.config.content_path.join(&collection.path);
+        let mut ctx = HandlerContext::new(&source, &feed_url, None, 1..=1,
+            false, &[]);
+
+        // Feeds have the newest pages, no matter how the collection sorts
+        let mut pages = query(website, &mut ctx, name,
+            &CollectionQuery::default()).await?;
+        pages.sort_by_key(|x| std::cmp::Reverse(x.1.time));
+        pages.truncate(feed.limit);
+
+        let entry = handler_template(website, &mut ctx, "feed_entry.xml")
+            .await?;
+        let mut entries = String::new();
+        for (url, page) in &pages {
+            entries += &feed_entry(&entry, base, url, page);
+        }
+
+        // The feed was last updated by its newest page
+        let updated = pages.first().map(|x| x.1.time)
+            .unwrap_or_else(Local::now);
+        let output = handler_template(website, &mut ctx, "feed.xml").await?
+            .replace("<<<PUT THE TITLE HERE>>>", &escape(&feed.title))
+            .replace("<<<PUT THE AUTHOR HERE>>>", &escape(&feed.author))
+            .replace("<<<PUT THE URL HERE>>>", &escape(&format!("{}/", base)))
+            .replace("<<<PUT THE FEED URL HERE>>>", &escape(&feed_url))
+            .replace("<<<PUT THE DATE HERE>>>", &updated.to_rfc3339())
+            .replace("<<<PUT THE ENTRIES HERE>>>", &entries);
+
+        // Pages can't be generated where the feed is
+        website.claim_output(&normalize_path(&path), &source)?;
+        ctx.add_output(path, output);
+
+        website.apply_context(ctx).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use chrono::{DateTime, Local, TimeZone};
+    use crate::{Error, TemplateInfo};
+    use super::{CollectionConfig, CollectionQuery, When};
+    use super::{select, parse_query, feed_entry};
+
+    /// Noon on `day` of August 2016
+    fn day(day: u32) -> DateTime<Local> {
+        Local.ymd(2016, 8, day).and_hms(12, 0, 0)
+    }
+
+    /// A page at `url` titled `title` from `day` of August 2016
+    fn page(url: &str, title: &str, time: u32) -> (String, TemplateInfo) {
+        (url.into(), TemplateInfo {
+            style: "style.css".into(),
+            template: "template.html".into(),
+            favicon: "favicon.ico".into(),
+            time: day(time),
+            title: title.into(),
+            description: String::new(),
+            slug: None,
+            permalink: None,
+        })
+    }
+
+    /// Select from the pages `b` on the 2nd, `c` and `a` on the 3rd, and
+    /// `d` on the 1st, in the collection `config`, as of the 2nd
+    fn urls(config: &str, query: &CollectionQuery) -> Vec<String> {
+        let config: CollectionConfig = toml::from_str(config).unwrap();
+        let pages = vec![page("/b", "Zebra", 2), page("/c", "Apple", 3),
+            page("/a", "Mango", 3), page("/d", "Kiwi", 1)];
+        select(&config, query, day(2), pages).into_iter()
+            .map(|x| x.0).collect()
+    }
+
+    #[test]
+    fn newest_first_by_default() {
+        let config = "path = \"blog\"";
+        assert_eq!(urls(config, &CollectionQuery::default()),
+            ["/c", "/a", "/b", "/d"]);
+        assert_eq!(urls(config, &CollectionQuery {
+            reverse: true,
+            limit: Some(3),
+            ..CollectionQuery::default()
+        }), ["/d", "/b", "/a"]);
+        assert_eq!(urls(config, &CollectionQuery {
+            limit: Some(0),
+            ..CollectionQuery::default()
+        }), Vec::<String>::new());
+    }
+
+    #[test]
+    fn sorts_by_title() {
+        let config = "path = \"blog\"\nsort = \"title\"\ndescending = false";
+        assert_eq!(urls(config, &CollectionQuery::default()),
+            ["/c", "/d", "/a", "/b"]);
+    }
+
+    #[test]
+    fn filters_by_time() {
+        let config = "path = \"talks\"";
+        assert_eq!(urls(config, &CollectionQuery {
+            when: Some(When::Upcoming),
+            ..CollectionQuery::default()
+        }), ["/c", "/a"]);
+        assert_eq!(urls(config, &CollectionQuery {
+            when: Some(When::Past),
+            reverse: true,
+            ..CollectionQuery::default()
+        }), ["/d", "/b"]);
+    }
+
+    #[test]
+    fn template_args() {
+        let source = Path::new("post.html");
+        let (name, query) = parse_query(source, "talks").unwrap();
+        assert_eq!(name, "talks");
+        assert_eq!((query.limit, query.when, query.reverse),
+            (None, None, false));
+
+        let (name, query) =
+            parse_query(source, " \"my talks\" reverse 5 upcoming ").unwrap();
+        assert_eq!(name, "my talks");
+        assert_eq!((query.limit, query.when, query.reverse),
+            (Some(5), Some(When::Upcoming), true));
+
+        for (args, bad) in [("blog five", "five"), ("blog -1", "-1"),
+                ("blog 3 future", "future")] {
+            assert!(matches!(parse_query(source, args),
+                Err(Error::BadCollectionQuery(path, x))
+                    if path == source && x == bad), "{}", args);
+        }
+    }
+
+    #[test]
+    fn feed_entries_are_escaped() {
+        let (url, mut page) = page("/blog/a&b.html", "<Fast> & \"safe\"", 2);
+        page.description = "x < y".into();
+        let entry = feed_entry("<<<PUT THE URL HERE>>>|<<<PUT THE TITLE HERE>>>\
+            |<<<PUT THE DESCRIPTION HERE>>>", "https://a.com", &url, &page);
+        assert_eq!(entry, "https://a.com/blog/a&amp;b.html|\
+            &lt;Fast&gt; &amp; &quot;safe&quot;|x &lt; y");
+    }
+}
@@ -0,0 +1,120 @@
+//! Website configuration
+
+use std::path::PathBuf;
+use std::collections::BTreeMap;
+use serde_derive::Deserialize;
+use pulldown_cmark::escape::{escape_href, escape_html};
+use crate::{ProcessHandlerConfig, PluginConfig, CollectionConfig};
+
+/// The config file for a website
+#[derive(Debug, Deserialize)]
+pub struct Config {
+    /// Theme to use with [`syntect::highlighting::ThemeSet`]
+    pub syntax_theme: String,
+
+    /// Directory to load all content from as a base directory
+    pub content_path: PathBuf,
+
+    /// Directory to output HTML files to
+    pub output_path: PathBuf,
+
+    /// Relative to `content_path`, provides the base file where all processing
+    /// starts
+    pub base_file: PathBuf,
+
+    /// Markdown file to use for the header
+    pub header_file: PathBuf,
+
+    /// Directory, relative to `content_path`, where `icon` shortcodes look up
+    /// icons by name
+    #[serde(default)]
+    pub icon_path: PathBuf,
+
+    /// Directory, relative to `content_path`, of TOML, JSON, and CSV files
+    /// which are loaded for the whole website. Defaults to `data`
+    #[serde(default = "default_data_path")]
+    pub data_path: PathBuf,
+
+    /// Named collections of pages, eg. a blog, keyed by name
+    #[serde(default)]
+    pub collections: BTreeMap<String, CollectionConfig>,
+
+    /// How links to other websites are rendered
+    #[serde(default)]
+    pub external_links: ExternalLinks,
+
+    /// Directory to keep the output of handlers in between builds, so blocks
+    /// which didn't change don't have to be handled again. There is no
+    /// caching if this isn't set
+    pub cache_path: Option<PathBuf>,
+
+    /// Names of the transforms to apply to markdown, in order. Transforms
+    /// which aren't listed are disabled. Defaults to every registered
+    /// transform, see [`crate::Transform`] for the built-in ones
+    pub transforms: Option<Vec<String>>,
+
+    /// Names of the rewriters to run over the HTML of every generated page,
+    /// in order. See [`crate::Rewriter`] for the built-in ones
+    #[serde(default)]
+    pub rewriters: Vec<String>,
+
+    /// Handlers implemented by external processes, keyed by handler name
+    #[serde(default)]
+    pub handlers: BTreeMap<String, ProcessHandlerConfig>,
+
+    /// Where WebAssembly plugins are loaded from and how they're limited
+    #[serde(default)]
+    pub plugins: PluginConfig,
+}
+
+/// Default directory of data files
+fn default_data_path() -> PathBuf {
+    PathBuf::from("data")
+}
+
+/// Decorations for links which leave the website
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ExternalLinks {
+    /// Add `rel="noopener"` to external links
+    pub noopener: bool,
+
+    /// HTML to place after the text of external links, eg. `↗`. This also
+    /// gives the link the `external` class
+    pub marker: Option<String>,
+}
+
+impl ExternalLinks {
+    /// Returns whether external links are decorated at all
+    pub(crate) fn enabled(&self) -> bool {
+        self.noopener || self.marker.is_some()
+    }
+
+    /// Create the opening `<a>` tag for a link to `dest` with `title`
+    pub(crate) fn open_tag(&self, dest: &str, title: &str) -> String {
+        let mut html = String::from("<a href=\"");
+        escape_href(&mut html, dest).unwrap();
+        html += "\"";
+        if !title.is_empty() {
+            html += " title=\"";
+            escape_html(&mut html, title).unwrap();
+            html += "\"";
+        }
+        if self.noopener {
+            html += " rel=\"noopener\"";
+        }
+        if self.marker.is_some() {
+            html += " class=\"external\"";
+        }
+        html += ">";
+        html
+    }
+}
+
+/// Options for a generation session which don't come from the config file
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Find the files under `content_path` which are never referenced, see
+    /// [`crate::BuildReport::unused`]
+    pub report_unused: bool,
+}
@@ -0,0 +1,185 @@
+//! Site-wide data files
+//!
+//! Every `.toml`, `.json`, and `.csv` file in `config.data_path` is loaded
+//! once when the website is built, and is named by its file stem, eg.
+//! `data/talks.toml` is `talks`. Values inside a file are looked up with a
+//! dotted key, where numbers index into arrays, eg. `talks.0.title`. CSV
+//! files are an array of rows, each mapping the column names to the cells.
+//!
+//! Data is available to:
+//!
+//! - handlers, with [`crate::Website::data`]
+//! - markdown, with the `{{ data <key> }}` shortcode
+//! - page templates, with `<<<PUT DATA <key> HERE>>>`
+//! - scripts, with `data(key)`
+//!
+//! Pages which use a data file depend on it, so changing it invalidates
+//! cached output which used it.
+
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+use async_trait::async_trait;
+use serde_json::Value;
+use pulldown_cmark::escape::escape_html;
+use crate::{Error, Result, Website, Handler, HandlerContext};
+use crate::shortcodes::split_args;
+
+/// A loaded data file
+pub(crate) struct DataFile {
+    /// Where the file was loaded from
+    pub(crate) path: PathBuf,
+
+    /// The contents of the file
+    pub(crate) value: Value,
+}
+
+/// Convert a TOML `value` into JSON, with dates as strings
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(x) => Value::String(x),
+        toml::Value::Integer(x) => x.into(),
+        toml::Value::Float(x) => x.into(),
+        toml::Value::Boolean(x) => x.into(),
+        toml::Value::Datetime(x) => Value::String(x.to_string()),
+        toml::Value::Array(x) =>
+            Value::Array(x.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(x) => Value::Object(x.into_iter()
+            .map(|(key, value)| (key, toml_to_json(value))).collect()),
+    }
+}
+
+/// Parse the data file at `path` with the `contents`, if it's a kind of data
+/// file
+fn parse(path: &Path, contents: &[u8]) -> Option<Result<Value>> {
+    let bad_data = |x: String| Error::ParseData(path.to_path_buf(), x);
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+
+    Some(match extension.as_str() {
+        "toml" => std::str::from_utf8(contents).map_err(|x| x.to_string())
+            .and_then(|x| toml::from_str(x).map_err(|x| x.to_string()))
+            .map(toml_to_json)
+            .map_err(bad_data),
+        "json" => serde_json::from_slice(contents)
+            .map_err(|x| bad_data(x.to_string())),
+        "csv" => {
+            let mut reader = csv::Reader::from_reader(contents);
+            reader.headers().cloned().and_then(|header| {
+                reader.records().map(|record| {
+                    Ok(Value::Object(header.iter().zip(record?.iter())
+                        .map(|(key, cell)| (key.into(), cell.into()))
+                        .collect()))
+                }).collect::<csv::Result<Vec<_>>>()
+            }).map(Value::Array).map_err(|x| bad_data(x.to_string()))
+        }
+        _ => return None,
+    })
+}
+
+/// Load every data file in the directory `path`, keyed by file stem. There's
+/// no data if the directory doesn't exist
+pub(crate) fn load(path: &Path) -> Result<BTreeMap<String, DataFile>> {
+    let mut data = BTreeMap::new();
+    if !path.is_dir() {
+        return Ok(data);
+    }
+
+    for dirent in std::fs::read_dir(path)
+            .map_err(|x| Error::ReadDirectory(path.to_path_buf(), x))? {
+        let path = dirent
+            .map_err(|x| Error::ReadDirectory(path.to_path_buf(), x))?.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let contents = std::fs::read(&path)
+            .map_err(|x| Error::ReadData(path.clone(), x))?;
+        let Some(value) = parse(&path, &contents) else { continue };
+        let value = value?;
+
+        // Two files with the same stem would be ambiguous
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        if let Some(existing) = data.get(&name) {
+            return Err(Error::DuplicateData(existing.path.clone(), path));
+        }
+        data.insert(name, DataFile { path, value });
+    }
+
+    Ok(data)
+}
+
+/// Look up the dotted `key` in the `data`, returning the file the value is in
+/// and the value
+pub(crate) fn lookup<'a>(data: &'a BTreeMap<String, DataFile>, key: &str)
+        -> Option<(&'a Path, &'a Value)> {
+    let mut parts = key.split('.');
+    let file = data.get(parts.next()?)?;
+
+    let mut value = &file.value;
+    for part in parts {
+        value = match value {
+            Value::Object(x) => x.get(part)?,
+            Value::Array(x) => x.get(part.parse::<usize>().ok()?)?,
+            _ => return None,
+        };
+    }
+    Some((&file.path, value))
+}
+
+/// Get the text for `value`, which is the string itself for strings and JSON
+/// for everything else
+pub(crate) fn to_text(value: &Value) -> String {
+    match value {
+        Value::String(x) => x.clone(),
+        x => x.to_string(),
+    }
+}
+
+/// Replace `<<<PUT DATA <key> HERE>>>` in the page `template` with the
+/// data, returning the data files which were used. Missing keys are an
+/// error, as the page would otherwise silently be missing something
+pub(crate) fn fill_template(data: &BTreeMap<String, DataFile>,
+        source: &Path, template: &str)
+        -> Result<(String, BTreeSet<PathBuf>)> {
+    const OPEN: &str = "<<<PUT DATA ";
+    const CLOSE: &str = " HERE>>>";
+
+    let mut filled = String::new();
+    let mut used = BTreeSet::new();
+    let mut rest = template;
+    while let Some(start) = rest.find(OPEN) {
+        let Some(end) = rest[start..].find(CLOSE) else { break };
+        let key = rest[start + OPEN.len()..start + end].trim();
+        let (path, value) = lookup(data, key)
+            .ok_or_else(|| Error::MissingData(source.to_path_buf(),
+                key.into()))?;
+
+        filled += &rest[..start];
+        filled += &to_text(value);
+        used.insert(path.to_path_buf());
+        rest = &rest[start + end + CLOSE.len()..];
+    }
+    filled += rest;
+
+    Ok((filled, used))
+}
+
+/// Renders a value from the data files, eg. `{{ data site.author }}`.
+/// Strings are shown as they are and anything else as JSON
+#[derive(Default)]
+pub(crate) struct Data;
+
+#[async_trait]
+impl Handler for Data {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let args = split_args(input);
+        let key = args.first().map(|x| x.as_str()).unwrap_or("");
+        let value = website.data(ctx, key).ok_or_else(||
+            Error::MissingData(ctx.source.to_path_buf(), key.into()))?;
+
+        let mut html = String::new();
+        escape_html(&mut html, &to_text(value)).unwrap();
+        Ok(html)
+    }
+}
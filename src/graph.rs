@@ -0,0 +1,330 @@
+//! Graphviz DOT graphs laid out and rendered into SVG at build time
+
+use std::sync::Arc;
+use std::fmt::Write;
+use async_trait::async_trait;
+use pulldown_cmark::escape::escape_html;
+use layout::core::color::Color;
+use layout::core::format::{ClipHandle, RenderBackend};
+use layout::core::geometry::Point;
+use layout::core::style::StyleAttr;
+use layout::gv::{DotParser, GraphBuilder};
+use crate::{Error, Result, Website, Handler, HandlerContext};
+use crate::handlers::{handler_template, theme_colors};
+
+/// Things to put after a prefix of a graph when finding where a parse error
+/// is. These cover what the parser can be expecting, so a prefix which
+/// could still be completed fails differently for at least one of them
+const CONTINUATIONS: &[&str] = &["", "}", "]", "x", "=", "->", "--", "[",
+    ";", ":"];
+
+/// Parse the DOT source `dot`
+fn parse(dot: &str) -> std::result::Result<layout::gv::parser::ast::Graph,
+        String> {
+    DotParser::new(dot).process()
+}
+
+/// Find the line of `dot` which caused the parse `error`. The parser doesn't
+/// say where it failed, so this finds the fewest lines which fail the same
+/// way no matter what comes after them
+fn error_line(dot: &str, error: &str) -> usize {
+    let lines = dot.lines().collect::<Vec<_>>();
+    for count in 1..lines.len() {
+        let prefix = lines[..count].join("\n");
+        if CONTINUATIONS.iter().all(|x| {
+            parse(&format!("{}\n{}", prefix, x)).err().as_deref() == Some(error)
+        }) {
+            return count;
+        }
+    }
+    lines.len().max(1)
+}
+
+/// Escape `text` for use in SVG
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    escape_html(&mut escaped, text).unwrap();
+    escaped
+}
+
+/// Renders a laid out graph into SVG, drawing it in the theme's colors
+/// unless the graph picks its own
+struct Canvas<'a> {
+    /// Prefix of ids in the SVG, so they're unique on the page
+    prefix: &'a str,
+
+    /// Text and line color of the theme
+    foreground: &'a str,
+
+    /// Background color of the theme
+    background: &'a str,
+
+    /// The shapes drawn so far
+    content: String,
+
+    /// Clip paths used by the shapes
+    clips: String,
+
+    /// Number of edges drawn so far, used to name their paths
+    edges: usize,
+
+    /// Size of the drawing
+    size: Point,
+}
+
+impl Canvas<'_> {
+    /// Grow the drawing to contain `size` from `point`
+    fn grow(&mut self, point: Point, size: Point) {
+        self.size.x = self.size.x.max(point.x + size.x + 5.0);
+        self.size.y = self.size.y.max(point.y + size.y + 5.0);
+    }
+
+    /// Get the CSS for `color`, where black and white mean the theme's
+    /// foreground and background
+    fn color(&self, color: Color) -> String {
+        match color.to_web_color().as_str() {
+            "#000000ff" => self.foreground.to_string(),
+            "#ffffffff" => self.background.to_string(),
+            x => x.to_string(),
+        }
+    }
+
+    /// Get the SVG for everything drawn
+    fn finish(self) -> String {
+        let (width, height) = (self.size.x, self.size.y);
+        format!("<svg class=\"graph\" xmlns=\"http://www.w3.org/2000/svg\" \
+            width=\"{width}\" height=\"{height}\" \
+            viewBox=\"0 0 {width} {height}\" role=\"img\" \
+            font-family=\"sans-serif\">\n<defs>\n\
+            <marker id=\"{prefix}-start\" markerWidth=\"10\" \
+            markerHeight=\"7\" refX=\"0\" refY=\"3.5\" orient=\"auto\">\
+            <polygon points=\"10 0, 10 7, 0 3.5\" fill=\"{fg}\"/></marker>\n\
+            <marker id=\"{prefix}-end\" markerWidth=\"10\" markerHeight=\"7\" \
+            refX=\"10\" refY=\"3.5\" orient=\"auto\">\
+            <polygon points=\"0 0, 10 3.5, 0 7\" fill=\"{fg}\"/></marker>\n\
+            {clips}</defs>\n{content}</svg>",
+            prefix = self.prefix, fg = self.foreground, clips = self.clips,
+            content = self.content)
+    }
+}
+
+impl RenderBackend for Canvas<'_> {
+    fn draw_rect(&mut self, xy: Point, size: Point, look: &StyleAttr,
+            clip: Option<ClipHandle>) {
+        self.grow(xy, size);
+        let fill = look.fill_color.map(|x| self.color(x))
+            .unwrap_or_else(|| "none".into());
+        let clip = clip.map(|x|
+            format!(" clip-path=\"url(#{}-clip{})\"", self.prefix, x))
+            .unwrap_or_default();
+        writeln!(self.content, "<rect x=\"{}\" y=\"{}\" width=\"{}\" \
+            height=\"{}\" rx=\"{}\" fill=\"{}\" stroke=\"{}\" \
+            stroke-width=\"{}\"{}/>", xy.x, xy.y, size.x, size.y,
+            look.rounded, fill, self.color(look.line_color), look.line_width,
+            clip).unwrap();
+    }
+
+    fn draw_line(&mut self, start: Point, stop: Point, look: &StyleAttr) {
+        writeln!(self.content, "<line x1=\"{}\" y1=\"{}\" x2=\"{}\" \
+            y2=\"{}\" stroke=\"{}\" stroke-width=\"{}\"/>",
+            start.x, start.y, stop.x, stop.y, self.color(look.line_color),
+            look.line_width).unwrap();
+    }
+
+    fn draw_circle(&mut self, xy: Point, size: Point, look: &StyleAttr) {
+        self.grow(xy, size);
+        let fill = look.fill_color.map(|x| self.color(x))
+            .unwrap_or_else(|| "none".into());
+        writeln!(self.content, "<ellipse cx=\"{}\" cy=\"{}\" rx=\"{}\" \
+            ry=\"{}\" fill=\"{}\" stroke=\"{}\" stroke-width=\"{}\"/>",
+            xy.x, xy.y, size.x / 2.0, size.y / 2.0, fill,
+            self.color(look.line_color), look.line_width).unwrap();
+    }
+
+    fn draw_text(&mut self, xy: Point, text: &str, look: &StyleAttr) {
+        // Each line is a tspan, with the block of them centered on `xy`
+        let lines = text.lines().count() + 1;
+        let y = xy.y - (lines * look.font_size) as f64 / 2.0;
+        let tspans = text.lines().map(|line| {
+            format!("<tspan x=\"{}\" dy=\"1.0em\">{}</tspan>", xy.x,
+                escape(line))
+        }).collect::<String>();
+
+        self.grow(xy, Point::new(10.0, text.len() as f64 * 10.0));
+        writeln!(self.content, "<text x=\"{}\" y=\"{}\" font-size=\"{}\" \
+            text-anchor=\"middle\" dominant-baseline=\"middle\" \
+            fill=\"{}\">{}</text>", xy.x, y, look.font_size,
+            self.color(look.line_color), tspans).unwrap();
+    }
+
+    fn draw_arrow(&mut self, path: &[(Point, Point)], dashed: bool,
+            head: (bool, bool), look: &StyleAttr, text: &str) {
+        for point in path {
+            self.grow(point.0, Point::zero());
+            self.grow(point.1, Point::zero());
+        }
+
+        // Degenerate edges, eg. of some self-loops, have nothing to draw
+        if path.len() < 2 {
+            return;
+        }
+
+        // The first point has the exit vector, and the rest have the entry
+        // vectors into them
+        let mut d = format!("M {} {} C {} {}, {} {}, {} {}",
+            path[0].0.x, path[0].0.y, path[0].1.x, path[0].1.y,
+            path[1].0.x, path[1].0.y, path[1].1.x, path[1].1.y);
+        for point in &path[2..] {
+            write!(d, " S {} {}, {} {}",
+                point.0.x, point.0.y, point.1.x, point.1.y).unwrap();
+        }
+
+        let mut attrs = String::new();
+        if dashed {
+            attrs += " stroke-dasharray=\"5,5\"";
+        }
+        if head.0 {
+            write!(attrs, " marker-start=\"url(#{}-start)\"", self.prefix)
+                .unwrap();
+        }
+        if head.1 {
+            write!(attrs, " marker-end=\"url(#{}-end)\"", self.prefix)
+                .unwrap();
+        }
+
+        // The label follows the edge
+        let id = format!("{}-edge{}", self.prefix, self.edges);
+        self.edges += 1;
+        writeln!(self.content, "<path id=\"{id}\" d=\"{d}\" fill=\"none\" \
+            stroke=\"{}\" stroke-width=\"{}\"{attrs}/>",
+            self.color(look.line_color), look.line_width).unwrap();
+        if !text.is_empty() {
+            writeln!(self.content, "<text font-size=\"{}\" fill=\"{}\">\
+                <textPath href=\"#{id}\" startOffset=\"50%\" \
+                text-anchor=\"middle\">{}</textPath></text>",
+                look.font_size, self.color(look.line_color), escape(text))
+                .unwrap();
+        }
+    }
+
+    fn create_clip(&mut self, xy: Point, size: Point, rounded_px: usize)
+            -> ClipHandle {
+        let handle = self.clips.matches("<clipPath").count();
+        writeln!(self.clips, "<clipPath id=\"{}-clip{}\"><rect x=\"{}\" \
+            y=\"{}\" width=\"{}\" height=\"{}\" rx=\"{}\"/></clipPath>",
+            self.prefix, handle, xy.x, xy.y, size.x, size.y, rounded_px)
+            .unwrap();
+        handle
+    }
+}
+
+/// Lays out and renders Graphviz DOT source into SVG, for ```` ```dot ````
+/// or ```` ```cuddly_graph ```` blocks, eg.
+///
+/// ```dot
+/// digraph {
+///     entry -> loop -> exit;
+///     loop -> loop [label="again"];
+/// }
+/// ```
+#[derive(Default)]
+pub(crate) struct Graph;
+
+#[async_trait]
+impl Handler for Graph {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        // The layout isn't `Send`, so load the template before it
+        let output = handler_template(website, ctx, "graph.html").await?;
+
+        // Parse the graph, pointing at the line which failed
+        let graph = parse(input).map_err(|x| {
+            let line = error_line(input, &x);
+            Error::BadHandlerInput(ctx.source.to_path_buf(), "graph".into(),
+                format!("{} on line {} of the graph (line {} of the file): \
+                    `{}`", x.trim_end_matches('.'), line,
+                    ctx.lines.start() + line,
+                    input.lines().nth(line - 1).unwrap_or("").trim()))
+        })?;
+        let mut builder = GraphBuilder::new();
+        builder.visit_graph(&graph);
+
+        // Ids in the SVG are named after the graph, so identical graphs
+        // share them harmlessly
+        let hash = blake3::hash(input.as_bytes()).to_hex();
+        let prefix = format!("graph-{}", &hash[..8]);
+
+        let (background, foreground) = theme_colors(website);
+        let mut canvas = Canvas {
+            prefix: &prefix,
+            foreground: &foreground,
+            background: &background,
+            content: String::new(),
+            clips: String::new(),
+            edges: 0,
+            size: Point::zero(),
+        };
+        builder.get().do_it(false, false, false, &mut canvas);
+        Ok(output.replace("<<<PUT THE GRAPH HERE>>>", &canvas.finish()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use layout::core::format::RenderBackend;
+    use layout::core::geometry::Point;
+    use layout::core::style::StyleAttr;
+    use layout::gv::GraphBuilder;
+    use super::{Canvas, parse, error_line};
+
+    /// Lay out and render the DOT source `dot`
+    fn render(dot: &str) -> String {
+        let mut builder = GraphBuilder::new();
+        builder.visit_graph(&parse(dot).unwrap());
+        let mut canvas = canvas();
+        builder.get().do_it(false, false, false, &mut canvas);
+        canvas.finish()
+    }
+
+    /// Create an empty canvas
+    fn canvas() -> Canvas<'static> {
+        Canvas {
+            prefix: "g",
+            foreground: "#000",
+            background: "#fff",
+            content: String::new(),
+            clips: String::new(),
+            edges: 0,
+            size: Point::zero(),
+        }
+    }
+
+    #[test]
+    fn self_loops() {
+        let svg = render("digraph { a -> b; b -> b [label=\"again\"]; }");
+        assert!(svg.starts_with("<svg class=\"graph\""));
+        assert!(svg.contains(">again</tspan>"));
+        assert!(svg.contains("<path id=\"g-edge1\""));
+    }
+
+    #[test]
+    fn short_edges_are_skipped() {
+        let mut canvas = canvas();
+        let look = StyleAttr::simple();
+        let point = (Point::new(1.0, 2.0), Point::new(3.0, 4.0));
+        canvas.draw_arrow(&[], false, (false, true), &look, "x");
+        canvas.draw_arrow(&[point], false, (false, true), &look, "x");
+        assert_eq!((canvas.content.as_str(), canvas.edges), ("", 0));
+
+        canvas.draw_arrow(&[point, point], true, (false, true), &look, "");
+        assert!(canvas.content.starts_with("<path id=\"g-edge0\" \
+            d=\"M 1 2 C 3 4, 1 2, 3 4\""));
+    }
+
+    #[test]
+    fn errors_point_at_their_line() {
+        let dot = "digraph {\n    a -> b;\n    c -> -> d;\n}";
+        let error = parse(dot).unwrap_err();
+        assert_eq!(error_line(dot, &error), 3);
+    }
+}
@@ -0,0 +1,696 @@
+//! The built-in `cuddly_*` handlers
+//!
+//! The markup they output comes from templates, which a site can override by
+//! putting its own in `templates/handlers` in `config.content_path`. The
+//! defaults are in `src/templates/handlers`.
+
+use std::sync::Arc;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use serde_derive::Deserialize;
+use pulldown_cmark::escape::escape_html;
+use chrono::format::{Item, StrftimeItems};
+use syntect::highlighting::{Color, Highlighter};
+use syntect::parsing::Scope;
+use crate::{Error, Result, Website, TemplateInfo, CollectionQuery};
+use crate::assets::AssetUsage;
+use crate::shortcodes::split_args;
+
+/// A handler for `cuddly_<name>` fenced code blocks, which replaces the block
+/// with the HTML it returns, or markdown if it calls
+/// [`HandlerContext::return_markdown`]
+#[async_trait]
+pub trait Handler: Send + Sync {
+    /// Render the contents of the block, `input`, into HTML
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+        ctx: &mut HandlerContext<'_>) -> Result<String>;
+
+    /// Whether the output can be reused for the same input on later builds,
+    /// as long as none of the dependencies declared in the context changed.
+    /// Handlers which affect the website in ways the context doesn't capture,
+    /// eg. by loading assets, must return `false`
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// Where a handler's block is being rendered, and a way for the handler to
+/// report things back to the website
+pub struct HandlerContext<'a> {
+    /// Markdown file which contains the block
+    pub source: &'a Path,
+
+    /// URL of the page being generated, eg. `/blog/blog1.html`
+    pub page_url: &'a str,
+
+    /// Metadata of the page being generated, if it has any
+    pub page: Option<&'a TemplateInfo>,
+
+    /// Lines of `source` which the block spans, including the fences
+    pub lines: RangeInclusive<usize>,
+
+    /// Whether the handler was invoked by an inline shortcode rather than a
+    /// code block, in which case the input is the shortcode's arguments and
+    /// the output goes inside a paragraph
+    pub inline: bool,
+
+    /// Canonical paths of the markdown being rendered, outermost first
+    include_stack: &'a [PathBuf],
+
+    /// Warnings emitted by the handler
+    pub(crate) warnings: Vec<String>,
+
+    /// Files the output of the handler depends on
+    pub(crate) dependencies: Vec<PathBuf>,
+
+    /// Files which need to be copied to the output directory
+    pub(crate) assets: Vec<PathBuf>,
+
+    /// Markdown files which need to be generated into pages
+    pub(crate) pages: Vec<PathBuf>,
+
+    /// Extra files to write, relative to `config.output_path`
+    pub(crate) outputs: Vec<(PathBuf, Vec<u8>)>,
+
+    /// Whether the handler returned markdown rather than HTML
+    pub(crate) markdown: bool,
+}
+
+impl<'a> HandlerContext<'a> {
+    /// Create a context for a block or shortcode spanning `lines` of `source`
+    pub(crate) fn new(source: &'a Path, page_url: &'a str,
+            page: Option<&'a TemplateInfo>, lines: RangeInclusive<usize>,
+            inline: bool, include_stack: &'a [PathBuf]) -> Self {
+        HandlerContext {
+            source, page_url, page, lines, inline, include_stack,
+            warnings:     Vec::new(),
+            dependencies: Vec::new(),
+            assets:       Vec::new(),
+            pages:        Vec::new(),
+            outputs:      Vec::new(),
+            markdown:     false,
+        }
+    }
+
+    /// Canonical paths of the markdown files being rendered, starting with the
+    /// page and ending with `source`
+    pub fn include_stack(&self) -> &[PathBuf] {
+        self.include_stack
+    }
+
+    /// Emit a warning about the block, which is reported with the build
+    pub fn warn(&mut self, message: impl Into<String>) {
+        self.warnings.push(message.into());
+    }
+
+    /// Declare that the output of the handler depends on the file at `path`
+    pub fn add_dependency(&mut self, path: impl Into<PathBuf>) {
+        self.dependencies.push(path.into());
+    }
+
+    /// Copy the file at `path`, which must be in `config.content_path`, to the
+    /// output directory
+    pub fn add_asset(&mut self, path: impl Into<PathBuf>) {
+        self.assets.push(path.into());
+    }
+
+    /// Generate the markdown at `path` into a page
+    pub fn add_page(&mut self, path: impl Into<PathBuf>) {
+        self.pages.push(path.into());
+    }
+
+    /// Declare that the handler returns markdown rather than HTML. The
+    /// markdown is rendered like the rest of the page, so it can contain raw
+    /// HTML, shortcodes, and more handler blocks
+    pub fn return_markdown(&mut self) {
+        self.markdown = true;
+    }
+
+    /// Write `contents` to `path`, relative to `config.output_path`
+    pub fn add_output(&mut self, path: impl Into<PathBuf>,
+            contents: impl Into<Vec<u8>>) {
+        self.outputs.push((path.into(), contents.into()));
+    }
+}
+
+/// Default templates for the output of the built-in handlers. A site can
+/// override them by putting a file with the same name in `templates/handlers`
+/// in `config.content_path`
+const DEFAULT_TEMPLATES: &[(&str, &str)] = &[
+    ("header.html",       include_str!("templates/handlers/header.html")),
+    ("header_left.html",  include_str!("templates/handlers/header_left.html")),
+    ("header_right.html",
+        include_str!("templates/handlers/header_right.html")),
+    ("index.html",        include_str!("templates/handlers/index.html")),
+    ("index_post.html",   include_str!("templates/handlers/index_post.html")),
+    ("post_link.html",    include_str!("templates/handlers/post_link.html")),
+    ("kbd.html",          include_str!("templates/handlers/kbd.html")),
+    ("hexdump.html",      include_str!("templates/handlers/hexdump.html")),
+    ("hexdump_row.html",
+        include_str!("templates/handlers/hexdump_row.html")),
+    ("hexdump_legend.html",
+        include_str!("templates/handlers/hexdump_legend.html")),
+    ("disasm.html",       include_str!("templates/handlers/disasm.html")),
+    ("disasm_row.html",
+        include_str!("templates/handlers/disasm_row.html")),
+    ("disasm_label.html",
+        include_str!("templates/handlers/disasm_label.html")),
+    ("terminal.html",
+        include_str!("templates/handlers/terminal.html")),
+    ("chart.html",        include_str!("templates/handlers/chart.html")),
+    ("graph.html",        include_str!("templates/handlers/graph.html")),
+    ("table.html",        include_str!("templates/handlers/table.html")),
+    ("feed.xml",          include_str!("templates/handlers/feed.xml")),
+    ("feed_entry.xml",    include_str!("templates/handlers/feed_entry.xml")),
+];
+
+/// Scopes whose colors in the syntax theme make up [`theme_palette`]
+const PALETTE_SCOPES: &[&str] = &[
+    "keyword", "string", "constant.numeric", "entity.name.function",
+    "storage.type", "support.function", "variable.parameter",
+    "constant.language",
+];
+
+/// Get the template `name` for the output of a built-in handler, preferring
+/// the site's override to the default
+pub(crate) async fn handler_template(website: &Website,
+        ctx: &mut HandlerContext<'_>, name: &str) -> Result<String> {
+    let path = website.config.content_path.join("templates/handlers")
+        .join(name);
+
+    // The output changes if an override gets added
+    ctx.add_dependency(&path);
+    match tokio::fs::read_to_string(&path).await {
+        Ok(template) => {
+            website.track_asset(&path, AssetUsage::Embedded);
+            Ok(template)
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let (_, template) = DEFAULT_TEMPLATES.iter()
+                .find(|(x, _)| *x == name).unwrap();
+            Ok(template.to_string())
+        }
+        Err(err) => Err(Error::ReadTemplate(ctx.source.to_path_buf(), path,
+            err)),
+    }
+}
+
+/// Format a syntect `color` for CSS
+pub(crate) fn css_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
+
+/// Get the background and foreground colors of the syntax theme, so output
+/// matches the code blocks
+pub(crate) fn theme_colors(website: &Website) -> (String, String) {
+    let settings = &website.theme.settings;
+    (css_color(settings.background.unwrap_or(Color::WHITE)),
+        css_color(settings.foreground.unwrap_or(Color::BLACK)))
+}
+
+/// Get colors from the syntax theme for telling things apart, eg. regions of
+/// a hexdump or series of a chart, so they match the code blocks
+pub(crate) fn theme_palette(website: &Website) -> Vec<String> {
+    let highlighter = Highlighter::new(&website.theme);
+    PALETTE_SCOPES.iter().map(|x| {
+        let scope = Scope::new(x).unwrap();
+        css_color(highlighter.style_for_stack(&[scope]).foreground)
+    }).collect()
+}
+
+/// Parse the block `input` of the handler `name` as TOML
+pub(crate) fn parse_config<T>(ctx: &HandlerContext, name: &str, input: &str)
+        -> Result<T> where T: serde::de::DeserializeOwned {
+    toml::from_str(input).map_err(|x| Error::BadHandlerInput(
+        ctx.source.to_path_buf(), name.into(), x.to_string()))
+}
+
+/// Load the CSV file at `path`, relative to `config.content_path`, for the
+/// handler `name`. Files ending in `.tsv` are tab separated unless
+/// `delimiter` says otherwise. Returns the header and the rows
+pub(crate) async fn load_csv(website: &Website, ctx: &mut HandlerContext<'_>,
+        name: &str, path: &Path, delimiter: Option<u8>)
+        -> Result<(Vec<String>, Vec<Vec<String>>)> {
+    let (path, _) = resolve_include(website, path).await?;
+    ctx.add_dependency(&path);
+    let csv = tokio::fs::read(&path).await
+        .map_err(|x| Error::ReadData(path.clone(), x))?;
+
+    let tsv = path.extension().is_some_and(|x| x == "tsv");
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter.unwrap_or(if tsv { b'\t' } else { b',' }))
+        .from_reader(&csv[..]);
+    let bad_input = |x: csv::Error| Error::BadHandlerInput(
+        ctx.source.to_path_buf(), name.into(), x.to_string());
+
+    let header = reader.headers().map_err(bad_input)?
+        .iter().map(String::from).collect();
+    let rows = reader.records().map(|record| {
+        Ok(record.map_err(bad_input)?.iter().map(String::from).collect())
+    }).collect::<Result<_>>()?;
+    Ok((header, rows))
+}
+
+#[derive(Default)]
+pub(crate) struct Header;
+
+#[derive(Debug, Deserialize)]
+struct HeaderConfig {
+    #[serde(default)]
+    left: Vec<(String, String)>,
+
+    #[serde(default)]
+    right: Vec<(String, String)>,
+}
+
+#[async_trait]
+impl Handler for Header {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let config: HeaderConfig = parse_config(ctx, "header", input)?;
+
+        let mut left = String::new();
+        let item = handler_template(website, ctx, "header_left.html").await?;
+        for (icon_path_or_name, href) in &config.left {
+            let asset = Self::load_icon(icon_path_or_name, website, ctx)
+                .await;
+            left += &item.replace("<<<PUT THE LINK HERE>>>", href)
+                .replace("<<<PUT THE ICON HERE>>>", &asset);
+        }
+
+        let mut right = String::new();
+        let item = handler_template(website, ctx, "header_right.html").await?;
+        for (icon_path_or_name, href) in &config.right {
+            let asset = Self::load_icon(icon_path_or_name, website, ctx)
+                .await;
+            right += &item.replace("<<<PUT THE LINK HERE>>>", href)
+                .replace("<<<PUT THE ICON HERE>>>", &asset);
+        }
+
+        // Very high quality templating
+        let output = handler_template(website, ctx, "header.html").await?;
+        let output = output.replace("<<<PUT THE LEFT LINKS HERE>>>", &left);
+        let output = output.replace("<<<PUT THE RIGHT LINKS HERE>>>", &right);
+
+        Ok(output)
+    }
+
+    /// Link icons are embedded with [`Website::load_asset`], which records
+    /// them as used assets outside the context
+    fn cacheable(&self) -> bool {
+        false
+    }
+}
+
+impl Header {
+    /// Load the asset at `icon_path_or_name`, falling back to using it as text
+    /// if it's not an asset
+    async fn load_icon(icon_path_or_name: &str, website: &Website,
+            ctx: &mut HandlerContext<'_>) -> String {
+        match website.load_asset(icon_path_or_name).await {
+            Ok(asset) => asset,
+            Err(err) => {
+                // Things with extensions were probably meant to be assets
+                if Path::new(icon_path_or_name).extension().is_some() {
+                    ctx.warn(format!("failed to load icon `{}`: {:?}",
+                        icon_path_or_name, err));
+                }
+                icon_path_or_name.to_string()
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Include;
+
+#[derive(Debug, Deserialize)]
+struct IncludeConfig {
+    /// Path to the file to include, relative to `config.content_path`
+    path: PathBuf,
+
+    /// Include the file as-is rather than processing it as markdown
+    #[serde(default)]
+    raw: bool,
+
+    /// Include the file as an escaped code block, highlighted as `lang` if
+    /// specified. Implies `raw`
+    #[serde(default)]
+    code: bool,
+
+    /// Language to highlight a `code` include as
+    lang: Option<String>,
+}
+
+#[async_trait]
+impl Handler for Include {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let config: IncludeConfig = parse_config(ctx, "include", input)?;
+
+        let (path, canon_path) = resolve_include(website, &config.path).await?;
+        ctx.add_dependency(&path);
+
+        // Raw includes don't get processed at all
+        if config.raw || config.code {
+            website.track_asset(&path, AssetUsage::Embedded);
+            let contents = tokio::fs::read_to_string(&path).await
+                .map_err(|x| Error::ReadMarkdownInput(path.clone(), x))?;
+
+            return Ok(if config.code {
+                website.highlight_code(&contents, config.lang.as_deref())
+            } else {
+                contents
+            });
+        }
+
+        render_include(website, ctx, path, canon_path).await
+    }
+
+    /// Included markdown is rendered into the website
+    fn cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// Resolve the include of `path`, relative to `config.content_path`, making
+/// sure it doesn't escape the content directory. Returns the path joined onto
+/// `content_path` and its canonical form
+pub(crate) async fn resolve_include(website: &Website, path: &Path)
+        -> Result<(PathBuf, PathBuf)> {
+    // Take into account `..` and symlinks
+    let path = website.config.content_path.join(path);
+    let canon_path = tokio::fs::canonicalize(&path).await
+        .map_err(|x| Error::ReadMarkdownInput(path.clone(), x))?;
+    let canon_content = tokio::fs::canonicalize(
+        &website.config.content_path).await
+        .map_err(|x| Error::ReadMarkdownInput(
+            website.config.content_path.clone(), x))?;
+    if !canon_path.starts_with(canon_content) {
+        return Err(Error::IncludeOutsideContent(path));
+    }
+
+    Ok((path, canon_path))
+}
+
+/// Render the markdown at `path`, as resolved by [`resolve_include`], as part
+/// of the block described by `ctx`
+pub(crate) async fn render_include(website: &Arc<Website>,
+        ctx: &HandlerContext<'_>, path: PathBuf, canon_path: PathBuf)
+        -> Result<String> {
+    // Refuse to include something which is already being included
+    if ctx.include_stack().contains(&canon_path) {
+        let mut chain = ctx.include_stack().to_vec();
+        chain.push(canon_path);
+        return Err(Error::IncludeCycle(chain));
+    }
+
+    // Included files are fragments and don't need a `templateinfo`
+    Ok(website.process_fragment(path, Some(ctx)).await?.0)
+}
+
+/// Lists posts, either every markdown file in a directory or pages picked
+/// from a collection, eg.
+///
+/// ```toml
+/// collection = "blog"
+/// limit = 5
+/// title = "Posts"
+/// year = 2024
+/// ```
+#[derive(Default)]
+pub(crate) struct Index;
+
+#[derive(Debug, Deserialize)]
+struct IndexConfig {
+    /// Directory of posts to list, relative to `config.content_path`
+    path: Option<PathBuf>,
+
+    /// Collection to list posts from, instead of a directory
+    collection: Option<String>,
+
+    /// Which posts of the collection to list
+    #[serde(flatten)]
+    query: CollectionQuery,
+
+    /// Heading of the list. Defaults to `Blogs`
+    #[serde(default = "default_index_title")]
+    title: String,
+
+    /// Year shown under the heading. Defaults to 2021
+    #[serde(default = "default_index_year")]
+    year: i32,
+}
+
+/// Default heading of a list of posts
+fn default_index_title() -> String {
+    "Blogs".into()
+}
+
+/// Default year shown on a list of posts
+fn default_index_year() -> i32 {
+    2021
+}
+
+#[async_trait]
+impl Handler for Index {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        // Get the config and the posts it lists
+        let config: IndexConfig = parse_config(ctx, "index", input)?;
+        let posts = match (&config.path, &config.collection) {
+            (Some(path), None) => list_posts(website, ctx,
+                &website.config.content_path.join(path)).await?,
+            (None, Some(name)) =>
+                website.collection(ctx, name, &config.query).await?,
+            _ => return Err(Error::BadHandlerInput(ctx.source.to_path_buf(),
+                "index".into(),
+                "expected one of `path` or `collection`".into())),
+        };
+
+        // Output HTML
+        let posts = render_posts(website, ctx, &posts).await?;
+        let mut title = String::new();
+        escape_html(&mut title, &config.title).unwrap();
+        let output = handler_template(website, ctx, "index.html").await?;
+        Ok(output.replace("<<<PUT THE TITLE HERE>>>", &title)
+            .replace("<<<PUT THE YEAR HERE>>>", &config.year.to_string())
+            .replace("<<<PUT THE POSTS HERE>>>", &posts))
+    }
+
+    /// Collections are ordered by the config and filtered by the time of the
+    /// build, which the context doesn't capture
+    fn cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// Render the `posts` as a list of links, from their URL and metadata
+pub(crate) async fn render_posts(website: &Website,
+        ctx: &mut HandlerContext<'_>, posts: &[(String, TemplateInfo)])
+        -> Result<String> {
+    let mut html = String::new();
+    let item = handler_template(website, ctx, "index_post.html").await?;
+    for (url, template_info) in posts {
+        let date = template_info.time.format("%B %d, %Y").to_string();
+        html += &item.replace("<<<PUT THE URL HERE>>>", url)
+            .replace("<<<PUT THE TITLE HERE>>>", &template_info.title)
+            .replace("<<<PUT THE DATE HERE>>>", &date);
+    }
+    Ok(html)
+}
+
+/// Get the URL and metadata of every markdown file in the directory `path`,
+/// making sure they all get generated into pages
+pub(crate) async fn list_posts(website: &Arc<Website>,
+        ctx: &mut HandlerContext<'_>, path: &Path)
+        -> Result<Vec<(String, TemplateInfo)>> {
+    let mut posts = Vec::new();
+
+    // Read the directory, the listing changes when posts are added
+    ctx.add_dependency(path);
+    let mut dir = tokio::fs::read_dir(path).await.map_err(|x|
+        Error::ReadDirectory(path.to_path_buf(), x))?;
+    while let Some(dirent) = dir.next_entry().await.map_err(|x|
+            Error::ReadDirectory(path.to_path_buf(), x))? {
+        let path = dirent.path();
+
+        // Skip non-markdown files
+        if path.extension().map(|x| x.eq_ignore_ascii_case("md")) !=
+                Some(true) {
+            continue;
+        }
+
+        // Read markdown metadata, the post is rendered when it's generated
+        let template_info = website.read_template_info(&path).await?
+            .ok_or_else(|| Error::TemplateInfoMissing(path.clone()))?;
+        ctx.add_dependency(&path);
+
+        // Make sure the post we're linking to gets generated
+        let url = website.output_file(&path, Some(&template_info))?.1;
+        ctx.add_page(&path);
+
+        posts.push((url, template_info));
+    }
+
+    Ok(posts)
+}
+
+/// Embeds an icon by name, eg. `{{ icon github }}` embeds `github.svg` from
+/// `config.icon_path`. An optional second argument overrides the title
+#[derive(Default)]
+pub(crate) struct Icon;
+
+#[async_trait]
+impl Handler for Icon {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let args = split_args(input);
+        let name = args.first().ok_or_else(|| Error::BadHandlerInput(
+            ctx.source.to_path_buf(), "icon".into(), "missing name".into()))?;
+
+        // Names without an extension are SVGs
+        let mut path = website.config.icon_path.join(name);
+        if path.extension().is_none() {
+            path.set_extension("svg");
+        }
+
+        // Names can't reach outside the content directory
+        resolve_include(website, &path).await?;
+
+        if path.extension().map(|x| x.eq_ignore_ascii_case("svg")) ==
+                Some(true) {
+            website.load_svg(&path, args.get(1).unwrap_or(name)).await
+        } else {
+            website.load_asset(&path).await
+        }
+    }
+
+    /// Icons are loaded into the website's sprite
+    fn cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// Links to a post by its file name, eg. `{% post_link blog1 %}` links to
+/// `blog1.md` wherever it is, titled by its `templateinfo`. An optional second
+/// argument overrides the text of the link
+#[derive(Default)]
+pub(crate) struct PostLink;
+
+#[async_trait]
+impl Handler for PostLink {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let args = split_args(input);
+        let slug = args.first().ok_or_else(|| Error::BadHandlerInput(
+            ctx.source.to_path_buf(), "post_link".into(),
+            "missing post name".into()))?;
+
+        // Find the post, the link changes if a post with this name is added
+        ctx.add_dependency(&website.config.content_path);
+        let posts = find_markdown(&website.config.content_path, slug).await?;
+        let path = posts.first().ok_or_else(|| Error::MissingLinkTarget(
+            ctx.source.to_path_buf(), slug.clone()))?;
+        if posts.len() > 1 {
+            ctx.warn(format!("`{}` is ambiguous, linking to {}", slug,
+                path.display()));
+        }
+
+        let title = match args.get(1) {
+            Some(text) => text.clone(),
+            None => website.read_template_info(path).await?
+                .ok_or_else(|| Error::TemplateInfoMissing(path.clone()))?
+                .title,
+        };
+        ctx.add_dependency(path);
+        ctx.add_page(path);
+
+        let mut escaped = String::new();
+        escape_html(&mut escaped, &title).unwrap();
+        let html = handler_template(website, ctx, "post_link.html").await?;
+        let url = website.output_url(path).await?;
+        Ok(html.replace("<<<PUT THE URL HERE>>>", &url)
+            .replace("<<<PUT THE TITLE HERE>>>", &escaped))
+    }
+}
+
+/// Find all markdown files under `dir` named `<stem>.md`, sorted by path
+async fn find_markdown(dir: &Path, stem: &str) -> Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(path) = dirs.pop() {
+        let mut dir = tokio::fs::read_dir(&path).await.map_err(|x|
+            Error::ReadDirectory(path.clone(), x))?;
+        while let Some(dirent) = dir.next_entry().await.map_err(|x|
+                Error::ReadDirectory(path.clone(), x))? {
+            let file_type = dirent.file_type().await.map_err(|x|
+                Error::ReadDirectory(path.clone(), x))?;
+
+            let path = dirent.path();
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if path.file_stem().map(|x| x == stem) == Some(true) &&
+                    path.extension().map(|x| x.eq_ignore_ascii_case("md")) ==
+                    Some(true) {
+                found.push(path);
+            }
+        }
+    }
+
+    found.sort();
+    Ok(found)
+}
+
+/// Renders keys, eg. `{{ kbd Ctrl C }}` becomes `<kbd>Ctrl</kbd>+<kbd>C</kbd>`
+#[derive(Default)]
+pub(crate) struct Kbd;
+
+#[async_trait]
+impl Handler for Kbd {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let template = handler_template(website, ctx, "kbd.html").await?;
+
+        let mut html = String::new();
+        for (ii, key) in split_args(input).iter().enumerate() {
+            if ii > 0 {
+                html += "+";
+            }
+            let mut escaped = String::new();
+            escape_html(&mut escaped, key).unwrap();
+            html += &template.replace("<<<PUT THE KEY HERE>>>", &escaped);
+        }
+        Ok(html)
+    }
+}
+
+/// Renders the time of the page, eg. `{{ date }}`, with an optional
+/// `strftime` format such as `{{ date "%Y-%m-%d" }}`
+#[derive(Default)]
+pub(crate) struct Date;
+
+#[async_trait]
+impl Handler for Date {
+    async fn handle(&self, input: &str, _website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let args = split_args(input);
+        let format = args.first().map(|x| x.as_str()).unwrap_or("%B %d, %Y");
+        let page = ctx.page.ok_or_else(|| Error::BadHandlerInput(
+            ctx.source.to_path_buf(), "date".into(),
+            "page has no templateinfo".into()))?;
+
+        // Invalid formats would panic when displayed
+        if StrftimeItems::new(format).any(|x| x == Item::Error) {
+            return Err(Error::BadHandlerInput(ctx.source.to_path_buf(),
+                "date".into(), format!("invalid format `{}`", format)));
+        }
+
+        let mut html = String::new();
+        escape_html(&mut html, &page.time.format(format).to_string())
+            .unwrap();
+        Ok(html)
+    }
+}
@@ -0,0 +1,218 @@
+//! An incredibly simple Markdown static site generator
+//!
+//! A [`Website`] is created from a [`Config`] with a [`WebsiteBuilder`],
+//! which is also where custom [`Handler`]s for `cuddly_*` code blocks are
+//! registered. Handlers can also be invoked from inside text with shortcodes
+//! such as `{{ icon github }}`. Markdown is run through a pipeline of
+//! [`Transform`]s, which can be reordered, disabled, or extended, and the
+//! final pages can be touched up by [`Rewriter`]s. Files in
+//! `config.data_path` are loaded once and shared with handlers, templates,
+//! and scripts, as are the pages of named collections such as a blog. Then
+//! [`Website::build`] generates the whole website.
+
+mod svg;
+mod assets;
+mod links;
+mod config;
+mod handlers;
+mod website;
+mod process;
+mod wasm;
+mod script;
+mod shortcodes;
+mod cache;
+mod transform;
+mod rewrite;
+mod binary;
+mod terminal;
+mod chart;
+mod graph;
+mod table;
+mod data;
+mod collection;
+mod permalink;
+
+use std::path::PathBuf;
+
+pub use config::{Config, ExternalLinks, Options};
+pub use process::{ProcessHandler, ProcessHandlerConfig};
+pub use wasm::{WasmHandler, PluginConfig};
+pub use script::ScriptHandler;
+pub use shortcodes::split_args;
+pub use handlers::{Handler, HandlerContext};
+pub use transform::{Transform, TransformContext, Events};
+pub use rewrite::{Rewriter, RewriteContext};
+pub use links::Link;
+pub use collection::{CollectionConfig, FeedConfig, CollectionQuery};
+pub use collection::{SortKey, When};
+pub use website::{Website, WebsiteBuilder, TemplateInfo, BuildReport};
+pub use website::{BrokenLink, Warning};
+
+/// Error types for this crate
+#[derive(Debug)]
+pub enum Error {
+    /// Reading an asset to base64 failed
+    ReadBase64Asset(PathBuf, std::io::Error),
+
+    /// Reading an SVG asset to inline failed
+    ReadSvg(PathBuf, std::io::Error),
+
+    /// Parsing an SVG asset failed
+    ParseSvg(PathBuf, roxmltree::Error),
+
+    /// An SVG asset did not have `<svg>` as its root element
+    NotSvg(PathBuf),
+
+    /// Failed to join with a tokio task responsible for processing a website
+    WebsiteJoin(tokio::task::JoinError),
+
+    /// Reading a config file failed
+    ConfigRead(PathBuf, std::io::Error),
+    
+    /// Parsing a config file failed
+    ConfigParse(PathBuf, toml::de::Error),
+
+    /// Stripping the prefix from the path failed, this could only occur if
+    /// the files are not correctly joined with the website's content path
+    StripPrefix(PathBuf, std::path::StripPrefixError),
+
+    /// An input had an unknown cuddly handler
+    MissingHandler(PathBuf, String),
+
+    /// Loading an additional syntax file failed
+    LoadSyntax(syntect::LoadingError),
+
+    /// The `syntax_theme` in the config is not a theme which syntect has
+    UnknownTheme(String),
+
+    /// `transforms` in the config names a transform which isn't registered
+    UnknownTransform(String),
+
+    /// `rewriters` in the config names a rewriter which isn't registered
+    UnknownRewriter(String),
+
+    /// The selector of the named rewriter couldn't be parsed
+    BadSelector(String, lol_html::errors::SelectorError),
+
+    /// Rewriting the HTML of the page generated from a markdown file failed
+    RewriteHtml(PathBuf, String),
+
+    /// Reading a file of bytes for a handler failed
+    ReadBinary(PathBuf, std::io::Error),
+
+    /// Reading a file of data for a handler failed
+    ReadData(PathBuf, std::io::Error),
+
+    /// A file in `config.data_path` couldn't be parsed
+    ParseData(PathBuf, String),
+
+    /// Two files in `config.data_path` have the same file stem, so they'd
+    /// have the same name
+    DuplicateData(PathBuf, PathBuf),
+
+    /// A markdown file or page template used a key which isn't in the data
+    /// files
+    MissingData(PathBuf, String),
+
+    /// A markdown file or page template used a collection which isn't in
+    /// `config.collections`
+    UnknownCollection(PathBuf, String),
+
+    /// A page template asked for a collection with an argument which isn't a
+    /// limit, `upcoming`, `past`, or `reverse`
+    BadCollectionQuery(PathBuf, String),
+
+    /// The permalink of a markdown file doesn't make a path in the output
+    /// directory
+    BadPermalink(PathBuf, String),
+
+    /// Two markdown files, or a markdown file and a collection's feed, would
+    /// be generated into the same file, given as the file relative to
+    /// `config.output_path` and the two sources. Feeds are given as their
+    /// collection's directory
+    PageCollision(PathBuf, PathBuf, PathBuf),
+
+    /// Creating the generated output directory failed
+    CreateOutputDir(PathBuf, std::io::Error),
+
+    /// Reading the directory failed
+    ReadDirectory(PathBuf, std::io::Error),
+
+    /// Reading the markdown input file failed
+    ReadMarkdownInput(PathBuf, std::io::Error),
+    
+    /// Reading the style file associated with a markdown file failed
+    ReadStyle(PathBuf, PathBuf, std::io::Error),
+    
+    /// Reading the template HTML file associated with a markdown file failed
+    ReadTemplate(PathBuf, PathBuf, std::io::Error),
+
+    /// Parsing template TOML information from a markdown file failed
+    ParseTemplateInfo(PathBuf, toml::de::Error),
+
+    /// Writing the output HTML failed
+    WriteOutput(PathBuf, std::io::Error),
+
+    /// A markdown file linked to another markdown file which doesn't exist
+    MissingLinkTarget(PathBuf, String),
+
+    /// An include resolved to a file outside of `content_path`
+    IncludeOutsideContent(PathBuf),
+
+    /// A file ended up including itself, this is the chain of includes which
+    /// caused it
+    IncludeCycle(Vec<PathBuf>),
+
+    /// Starting the process for a process handler failed
+    SpawnPlugin(String, std::io::Error),
+
+    /// Communicating with the process for a process handler failed
+    PluginIo(String, std::io::Error),
+
+    /// The process for a process handler did not respond in time
+    PluginTimeout(String),
+
+    /// A process handler's request or response was not valid JSON
+    PluginProtocol(String, serde_json::Error),
+
+    /// A plugin handler reported that it failed, or a WebAssembly plugin
+    /// trapped or ran out of fuel
+    PluginFailed(String, String),
+
+    /// Reading a WebAssembly plugin failed
+    ReadPlugin(PathBuf, std::io::Error),
+
+    /// Compiling a WebAssembly plugin failed
+    LoadPlugin(PathBuf, wasmi::Error),
+
+    /// Reading a handler script failed
+    ReadScript(PathBuf, std::io::Error),
+
+    /// A handler script has a syntax error
+    ParseScript(PathBuf, rhai::ParseError),
+
+    /// Handlers returning markdown which invokes handlers went too deep, at
+    /// the line of the outermost block in the markdown file
+    HandlerDepth(PathBuf, usize),
+
+    /// Writing an entry to the handler cache failed
+    WriteCache(PathBuf, std::io::Error),
+
+    /// A built-in handler in the markdown file was given input it doesn't
+    /// understand, along with the name of the handler and the problem
+    BadHandlerInput(PathBuf, String, String),
+
+    /// Copying a referenced asset to the output directory failed
+    CopyAsset(PathBuf, PathBuf, std::io::Error),
+
+    /// A markdown file did not have a `templateinfo` section
+    TemplateInfoMissing(PathBuf),
+
+    /// Internal links pointed to pages or anchors which do not exist. The
+    /// library only reports these in the [`BuildReport`], it's up to the user
+    /// to decide if they're fatal
+    BrokenLinks(usize),
+}
+
+/// Convenient `Result` wrapper around our `Error` type
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,235 @@
+//! Internal link checking of the generated website
+
+use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+use crate::assets;
+
+/// An internal link found while generating the website
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Link {
+    /// File which the link came from (markdown or template)
+    pub source: PathBuf,
+
+    /// 1-indexed line in `source` where the link is
+    pub line: usize,
+
+    /// The link as written in the source
+    pub dest: String,
+
+    /// The link resolved to an absolute URL, eg. `/blog/blog1.html#intro`
+    pub url: String,
+}
+
+/// Convert a relative file path into the `/` separated form used in URLs
+pub fn url_path(path: impl AsRef<Path>) -> String {
+    path.as_ref().components()
+        .map(|x| x.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>().join("/")
+}
+
+/// Get the 1-indexed line number of the byte `offset` into `text`
+pub fn line_of(text: &str, offset: usize) -> usize {
+    text.as_bytes()[..offset.min(text.len())].iter()
+        .filter(|&&x| x == b'\n').count() + 1
+}
+
+/// Determine if `dest` is a link to another website
+pub fn is_external(dest: &str) -> bool {
+    dest.starts_with("http://") || dest.starts_with("https://") ||
+        dest.starts_with("//")
+}
+
+/// Find the values of all `href` and `src` attributes in `html`, along with
+/// their byte offsets into `html`. SVG `<use>` references to sprite symbols
+/// are not links, and are skipped
+pub fn find_urls(html: &str) -> Vec<(usize, &str)> {
+    let mut urls = Vec::new();
+    for attr in [" href=\"", " src=\""] {
+        urls.extend(find_attribute(html, attr).filter(|(offset, _)| {
+            let tag = html[..*offset].rsplit('<').next().unwrap();
+            tag != "use" && !tag.starts_with("use ")
+        }));
+    }
+    urls.sort();
+    urls
+}
+
+/// Find the values of all `id` attributes in `html`
+pub fn find_ids(html: &str) -> BTreeSet<String> {
+    find_attribute(html, " id=\"").map(|(_, x)| x.to_string()).collect()
+}
+
+/// Find the values of all double-quoted attributes starting with `attr` (eg.
+/// ` href="`) in `html`, along with their offsets
+fn find_attribute<'a>(html: &'a str, attr: &'a str)
+        -> impl Iterator<Item = (usize, &'a str)> + 'a {
+    html.match_indices(attr).filter_map(move |(offset, _)| {
+        let value = &html[offset + attr.len()..];
+        value.find('"').map(|end| (offset, &value[..end]))
+    })
+}
+
+/// Create an id for a heading from its text, eg. `Hello world!` becomes
+/// `hello-world`
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for chr in text.chars().flat_map(char::to_lowercase) {
+        if chr.is_alphanumeric() || chr == '_' {
+            slug.push(chr);
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+
+    // Trim trailing separator
+    while slug.ends_with('-') {
+        slug.pop();
+    }
+    slug
+}
+
+/// Resolve `dest` as found on the page at `base_url` into an absolute URL.
+/// Returns `None` if the link is not internal
+pub fn resolve_url(base_url: &str, dest: &str) -> Option<String> {
+    // Links which are only a fragment point into the page itself
+    if let Some(fragment) = dest.strip_prefix('#') {
+        return Some(format!("{}#{}", base_url, fragment));
+    }
+
+    // Templates contain placeholders which will be replaced later
+    if !assets::is_local(dest) || dest.contains("<<<") {
+        return None;
+    }
+
+    // Split off the fragment and query string
+    let (dest, fragment) = match dest.find('#') {
+        Some(idx) => (&dest[..idx], &dest[idx..]),
+        None      => (dest, ""),
+    };
+    let dest = dest.split('?').next().unwrap();
+
+    // Relative links are based on the directory of the page, except ones
+    // which are only a query string, which point at the page itself
+    let joined = if dest.starts_with('/') {
+        dest.to_string()
+    } else if dest.is_empty() {
+        base_url.to_string()
+    } else {
+        format!("{}{}", &base_url[..base_url.rfind('/').unwrap() + 1], dest)
+    };
+
+    // Resolve `.` and `..`
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in joined.split('/').skip(1) {
+        match segment {
+            "."  => {}
+            ".." => { segments.pop(); }
+            _    => segments.push(segment),
+        }
+    }
+
+    // Preserve a trailing slash, which means the index of a directory
+    let mut url = format!("/{}", segments.join("/"));
+    if (joined.ends_with("/.") || joined.ends_with("/..")) &&
+            !url.ends_with('/') {
+        url.push('/');
+    }
+    url += fragment;
+    Some(url)
+}
+
+/// Check that `url` resolves to one of the `outputs`, which map files relative
+/// to the output directory to the ids of the elements in them. Returns a
+/// description of the problem if the link is broken
+pub fn check_url(url: &str, outputs: &BTreeMap<String, BTreeSet<String>>)
+        -> Option<String> {
+    let (path, fragment) = match url.find('#') {
+        Some(idx) => (&url[..idx], Some(&url[idx + 1..])),
+        None      => (url, None),
+    };
+    let path = path.trim_start_matches('/');
+
+    // Figure out which file a web server would serve for this path
+    let candidates = if path.is_empty() || path.ends_with('/') {
+        vec![format!("{}index.html", path)]
+    } else {
+        vec![path.to_string(), format!("{}.html", path),
+            format!("{}/index.html", path)]
+    };
+    let ids = match candidates.iter().find_map(|x| outputs.get(x)) {
+        Some(ids) => ids,
+        None      => return Some("page does not exist".into()),
+    };
+
+    match fragment {
+        Some(fragment) if !fragment.is_empty() &&
+                !ids.contains(fragment) => {
+            Some(format!("no element with id `{}`", fragment))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+    use super::{resolve_url, check_url};
+
+    /// Resolve `dest` as found on `/blog/x.html`
+    fn resolve(dest: &str) -> Option<String> {
+        resolve_url("/blog/x.html", dest)
+    }
+
+    #[test]
+    fn relative_links() {
+        assert_eq!(resolve("y.html").unwrap(), "/blog/y.html");
+        assert_eq!(resolve("./img/a.png").unwrap(), "/blog/img/a.png");
+        assert_eq!(resolve("../favicon.ico").unwrap(), "/favicon.ico");
+        assert_eq!(resolve("../../../a").unwrap(), "/a");
+        assert_eq!(resolve("/about/").unwrap(), "/about/");
+    }
+
+    #[test]
+    fn directories_keep_their_slash() {
+        assert_eq!(resolve(".").unwrap(), "/blog/");
+        assert_eq!(resolve("..").unwrap(), "/");
+        assert_eq!(resolve("../blog/.").unwrap(), "/blog/");
+    }
+
+    #[test]
+    fn queries_and_fragments() {
+        assert_eq!(resolve("#intro").unwrap(), "/blog/x.html#intro");
+        assert_eq!(resolve("y.html?a=1#b").unwrap(), "/blog/y.html#b");
+        assert_eq!(resolve("?page=2").unwrap(), "/blog/x.html");
+        assert_eq!(resolve("?page=2#b").unwrap(), "/blog/x.html#b");
+    }
+
+    #[test]
+    fn external_links_are_skipped() {
+        for dest in ["https://a.com", "//a.com/x", "mailto:a@b.com", "",
+                "<<<PUT THE URL HERE>>>"] {
+            assert_eq!(resolve(dest), None, "{}", dest);
+        }
+    }
+
+    #[test]
+    fn checks_pages_and_ids() {
+        let outputs: BTreeMap<String, BTreeSet<String>> = [
+            ("index.html", &["top"][..]),
+            ("blog/x.html", &["intro"][..]),
+            ("about/index.html", &[][..]),
+        ].iter().map(|(path, ids)| (path.to_string(),
+            ids.iter().map(|x| x.to_string()).collect())).collect();
+
+        for url in ["/", "/#top", "/blog/x.html#intro", "/blog/x#intro",
+                "/about", "/about/", "/blog/x.html#"] {
+            assert_eq!(check_url(url, &outputs), None, "{}", url);
+        }
+        assert_eq!(check_url("/blog/x.html#nope", &outputs).unwrap(),
+            "no element with id `nope`");
+        for url in ["/missing", "/blog/", "/blog/x.htm"] {
+            assert_eq!(check_url(url, &outputs).unwrap(),
+                "page does not exist", "{}", url);
+        }
+    }
+}
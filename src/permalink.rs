@@ -0,0 +1,189 @@
+//! Permalinks, which say where pages are generated
+//!
+//! A permalink pattern is a path under `config.output_path`, made from text
+//! and these placeholders:
+//!
+//! - `{dir}`: the directory of the markdown, relative to
+//!   `config.content_path`
+//! - `{slug}`: `templateinfo.slug`, or the file name of the markdown without
+//!   its extension
+//! - `{year}`, `{month}`, `{day}`: the date of `templateinfo.time`
+//!
+//! Patterns ending in `/` generate `index.html` in that directory, so the
+//! page's URL doesn't have an extension, eg. `/blog/{year}/{slug}/`. Other
+//! patterns are the file itself, which gets `.html` if it has no extension.
+//! Pages which don't have a pattern use [`DEFAULT`].
+
+use std::path::{Path, PathBuf};
+use std::collections::HashMap;
+use crate::TemplateInfo;
+use crate::links::url_path;
+
+/// Pattern of pages which don't have one, keeping the layout of the content
+pub(crate) const DEFAULT: &str = "{dir}/{slug}.html";
+
+/// Expand `pattern` for the markdown at `relative`, relative to
+/// `config.content_path`, with the metadata `template_info`. Returns the
+/// file to generate, relative to `config.output_path`, and its URL, or a
+/// description of what's wrong with the pattern
+pub(crate) fn expand(pattern: &str, relative: &Path,
+        template_info: &TemplateInfo)
+        -> std::result::Result<(PathBuf, String), String> {
+    // The slug has to stay a single segment of the path
+    let stem = relative.file_stem().unwrap_or_default().to_string_lossy();
+    let slug = template_info.slug.as_deref().unwrap_or(&stem);
+    if slug.is_empty() || slug == "." || slug == ".." ||
+            slug.contains(['/', '\\']) {
+        return Err(format!("slug `{}` isn't a file name", slug));
+    }
+
+    // Fill in the placeholders
+    let dir = url_path(relative.parent().unwrap_or(Path::new("")));
+    let mut expanded = String::new();
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..].find('}')
+            .ok_or_else(|| format!("unclosed `{{` in `{}`", pattern))?;
+        expanded += &rest[..start];
+        expanded += &match &rest[start + 1..start + end] {
+            "dir" => dir.clone(),
+            "slug" => slug.to_string(),
+            "year" => template_info.time.format("%Y").to_string(),
+            "month" => template_info.time.format("%m").to_string(),
+            "day" => template_info.time.format("%d").to_string(),
+            x => return Err(format!("unknown placeholder `{{{}}}`", x)),
+        };
+        rest = &rest[start + end + 1..];
+    }
+    expanded += rest;
+
+    // Split it into the path, which must stay in the output directory
+    let mut segments = Vec::new();
+    for segment in expanded.split('/').filter(|x| !x.is_empty()) {
+        if segment == "." || segment == ".." || segment.contains('\\') {
+            return Err(format!("`{}` leaves its directory", expanded));
+        }
+        segments.push(segment.to_string());
+    }
+
+    // Directories get an index, and files are HTML unless they say otherwise
+    if expanded.ends_with('/') || segments.is_empty() {
+        let url = segments.iter().map(|x| format!("/{}", x))
+            .collect::<String>() + "/";
+        segments.push("index.html".into());
+        Ok((segments.iter().collect(), url))
+    } else {
+        let last = segments.last_mut().unwrap();
+        if Path::new(last.as_str()).extension().is_none() {
+            *last += ".html";
+        }
+        Ok((segments.iter().collect(), format!("/{}", segments.join("/"))))
+    }
+}
+
+/// Record that the page `file`, relative to `config.output_path`, is
+/// generated from the markdown at `source`. Returns the markdown which
+/// already generates `file`, as the two pages would overwrite each other
+pub(crate) fn claim(sources: &mut HashMap<PathBuf, PathBuf>, file: &Path,
+        source: &Path) -> std::result::Result<(), PathBuf> {
+    match sources.insert(file.to_path_buf(), source.to_path_buf()) {
+        Some(other) if other != source => Err(other),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::collections::HashMap;
+    use chrono::TimeZone;
+    use crate::TemplateInfo;
+    use super::{expand, claim, DEFAULT};
+
+    /// Metadata of a page from 2016-08-13 with the `slug` override
+    fn info(slug: Option<&str>) -> TemplateInfo {
+        TemplateInfo {
+            style: "style.css".into(),
+            template: "template.html".into(),
+            favicon: "favicon.ico".into(),
+            time: chrono::Local.ymd(2016, 8, 13).and_hms(12, 0, 0),
+            title: "Title".into(),
+            description: "Description".into(),
+            slug: slug.map(Into::into),
+            permalink: None,
+        }
+    }
+
+    /// Expand `pattern` for `relative` without a slug override
+    fn expand_path(pattern: &str, relative: &str)
+            -> Result<(PathBuf, String), String> {
+        expand(pattern, Path::new(relative), &info(None))
+    }
+
+    #[test]
+    fn default_keeps_layout() {
+        assert_eq!(expand_path(DEFAULT, "blog/x.md").unwrap(),
+            (PathBuf::from("blog/x.html"), "/blog/x.html".into()));
+        assert_eq!(expand_path(DEFAULT, "index.md").unwrap(),
+            (PathBuf::from("index.html"), "/index.html".into()));
+    }
+
+    #[test]
+    fn directories_get_an_index() {
+        assert_eq!(expand_path("/blog/{year}/{slug}/", "blog/x.md").unwrap(),
+            (PathBuf::from("blog/2016/x/index.html"), "/blog/2016/x/".into()));
+        assert_eq!(expand_path("/", "x.md").unwrap(),
+            (PathBuf::from("index.html"), "/".into()));
+    }
+
+    #[test]
+    fn dates_and_extensions() {
+        assert_eq!(expand_path("{year}-{month}-{day}/{slug}", "x.md")
+            .unwrap(), (PathBuf::from("2016-08-13/x.html"),
+                "/2016-08-13/x.html".into()));
+        assert_eq!(expand_path("{slug}.xml", "x.md").unwrap(),
+            (PathBuf::from("x.xml"), "/x.xml".into()));
+    }
+
+    #[test]
+    fn slug_overrides_file_name() {
+        assert_eq!(expand(DEFAULT, Path::new("blog/x.md"),
+            &info(Some("hello"))).unwrap(),
+            (PathBuf::from("blog/hello.html"), "/blog/hello.html".into()));
+        for slug in ["", ".", "..", "a/b", "a\\b"] {
+            assert!(expand(DEFAULT, Path::new("x.md"), &info(Some(slug)))
+                .unwrap_err().contains("isn't a file name"));
+        }
+    }
+
+    #[test]
+    fn stays_in_output_directory() {
+        for pattern in ["../{slug}", "{dir}/./{slug}", "a\\..\\{slug}"] {
+            assert!(expand_path(pattern, "blog/x.md").unwrap_err()
+                .contains("leaves its directory"));
+        }
+    }
+
+    #[test]
+    fn bad_placeholders() {
+        assert_eq!(expand_path("{title}", "x.md").unwrap_err(),
+            "unknown placeholder `{title}`");
+        assert_eq!(expand_path("{slug", "x.md").unwrap_err(),
+            "unclosed `{` in `{slug`");
+    }
+
+    #[test]
+    fn collisions() {
+        // Two pages with the same slug land in the same place
+        let pattern = "/posts/{slug}/";
+        let (a, _) = expand_path(pattern, "blog/x.md").unwrap();
+        let (b, _) = expand_path(pattern, "talks/x.md").unwrap();
+        assert_eq!(a, b);
+
+        let mut sources = HashMap::new();
+        claim(&mut sources, &a, Path::new("blog/x.md")).unwrap();
+        claim(&mut sources, &a, Path::new("blog/x.md")).unwrap();
+        assert_eq!(claim(&mut sources, &b, Path::new("talks/x.md")),
+            Err(PathBuf::from("blog/x.md")));
+    }
+}
@@ -0,0 +1,246 @@
+//! Handlers implemented by external processes
+//!
+//! The process is started on first use and reused for every block it handles.
+//! Each block is sent as one line of JSON on the process's stdin, and the
+//! process answers with one line of JSON on its stdout:
+//!
+//! ```text
+//! -> {"handler":"graph","input":"...","source":"content/index.md",
+//!     "page_url":"/index.html","page":{...},"lines":[12,16],
+//!     "inline":false}
+//! <- {"html":"<svg>...</svg>","dependencies":["data/graph.csv"],
+//!     "diagnostics":["something looks off"]}
+//! ```
+//!
+//! Instead of `html` the response can have `markdown`, which is rendered like
+//! the rest of the page. `dependencies` are relative to `config.content_path`,
+//! and `diagnostics` become warnings. A response with an `error` fails the
+//! build.
+
+use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use async_trait::async_trait;
+use serde_derive::{Serialize, Deserialize};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use crate::{Error, Result, Website, Handler, HandlerContext, TemplateInfo};
+
+/// Configuration for a handler implemented by an external process, from the
+/// `[handlers.<name>]` section of the config
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProcessHandlerConfig {
+    /// Program and arguments to run, relative to the working directory
+    pub command: Vec<String>,
+
+    /// Number of seconds to wait for a response before killing the process
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+/// Default timeout for a process handler to respond, in seconds
+fn default_timeout() -> u64 {
+    10
+}
+
+/// A block sent to the process
+#[derive(Serialize)]
+pub(crate) struct Request<'a> {
+    /// Name of the handler, ie. `<name>` in `cuddly_<name>`
+    handler: &'a str,
+
+    /// Contents of the block
+    input: &'a str,
+
+    /// Markdown file containing the block
+    source: &'a Path,
+
+    /// URL of the page being generated
+    page_url: &'a str,
+
+    /// Metadata of the page being generated
+    page: Option<&'a TemplateInfo>,
+
+    /// First and last lines of the block in `source`
+    lines: (usize, usize),
+
+    /// Whether this is an inline shortcode rather than a block
+    inline: bool,
+}
+
+/// What the process responded with
+#[derive(Deserialize)]
+pub(crate) struct Response {
+    /// HTML to replace the block with
+    #[serde(default)]
+    html: String,
+
+    /// Markdown to replace the block with, used instead of `html` if set
+    markdown: Option<String>,
+
+    /// Files the HTML depends on, relative to `config.content_path`
+    #[serde(default)]
+    dependencies: Vec<PathBuf>,
+
+    /// Warnings about the block
+    #[serde(default)]
+    diagnostics: Vec<String>,
+
+    /// If set, the block could not be handled
+    error: Option<String>,
+}
+
+impl<'a> Request<'a> {
+    /// Serialize the block `input`, found where `ctx` says, for the handler
+    /// named `handler`
+    pub(crate) fn to_json(handler: &str, input: &str, ctx: &HandlerContext)
+            -> Result<String> {
+        serde_json::to_string(&Request {
+            source:   ctx.source,
+            page_url: ctx.page_url,
+            page:     ctx.page,
+            lines:    (*ctx.lines.start(), *ctx.lines.end()),
+            inline:   ctx.inline,
+            handler,
+            input,
+        }).map_err(|x| Error::PluginProtocol(handler.into(), x))
+    }
+}
+
+impl Response {
+    /// Parse the JSON `response` from the handler named `handler`, applying
+    /// it to `ctx` and returning the HTML
+    pub(crate) fn apply(handler: &str, response: &str, website: &Website,
+            ctx: &mut HandlerContext) -> Result<String> {
+        let response: Response = serde_json::from_str(response)
+            .map_err(|x| Error::PluginProtocol(handler.into(), x))?;
+        if let Some(error) = response.error {
+            return Err(Error::PluginFailed(handler.into(), error));
+        }
+
+        for dependency in response.dependencies {
+            ctx.add_dependency(website.config.content_path.join(dependency));
+        }
+        for diagnostic in response.diagnostics {
+            ctx.warn(diagnostic);
+        }
+
+        if let Some(markdown) = response.markdown {
+            ctx.return_markdown();
+            return Ok(markdown);
+        }
+        Ok(response.html)
+    }
+}
+
+/// A running handler process
+struct Process {
+    /// The process itself, killed when this is dropped
+    _child: Child,
+
+    /// Where requests are written
+    stdin: ChildStdin,
+
+    /// Where responses are read from
+    stdout: BufReader<ChildStdout>,
+}
+
+impl Process {
+    /// Send a `request` line and wait for the response line
+    async fn exchange(&mut self, request: &str) -> std::io::Result<String> {
+        self.stdin.write_all(request.as_bytes()).await?;
+        self.stdin.flush().await?;
+
+        let mut response = String::new();
+        if self.stdout.read_line(&mut response).await? == 0 {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(response)
+    }
+}
+
+/// A handler which forwards blocks to an external process
+pub struct ProcessHandler {
+    /// Name of the handler, used for error reporting
+    name: String,
+
+    /// How to run the process
+    config: ProcessHandlerConfig,
+
+    /// The process, if it's running. Blocks are handled one at a time
+    process: tokio::sync::Mutex<Option<Process>>,
+}
+
+impl ProcessHandler {
+    /// Create a handler named `name`, the process is not started until the
+    /// first block is handled
+    pub fn new(name: impl Into<String>, config: ProcessHandlerConfig) -> Self {
+        ProcessHandler {
+            name:    name.into(),
+            process: tokio::sync::Mutex::new(None),
+            config,
+        }
+    }
+
+    /// Start the process
+    fn spawn(&self) -> Result<Process> {
+        let (program, args) = self.config.command.split_first()
+            .ok_or_else(|| Error::PluginFailed(self.name.clone(),
+                "empty command".into()))?;
+
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|x| Error::SpawnPlugin(self.name.clone(), x))?;
+
+        Ok(Process {
+            stdin:  child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            _child: child,
+        })
+    }
+}
+
+#[async_trait]
+impl Handler for ProcessHandler {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let mut request = Request::to_json(&self.name, input, ctx)?;
+        request += "\n";
+
+        // Start the process if it's not already running
+        let mut process = self.process.lock().await;
+        if process.is_none() {
+            *process = Some(self.spawn()?);
+        }
+
+        // If anything goes wrong, the process is killed so we don't get out
+        // of sync with it, and it'll be restarted for the next block
+        let timeout = Duration::from_secs(self.config.timeout);
+        let response = match tokio::time::timeout(timeout,
+                process.as_mut().unwrap().exchange(&request)).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(err)) => {
+                *process = None;
+                return Err(Error::PluginIo(self.name.clone(), err));
+            }
+            Err(_) => {
+                *process = None;
+                return Err(Error::PluginTimeout(self.name.clone()));
+            }
+        };
+        drop(process);
+
+        Response::apply(&self.name, &response, website, ctx)
+    }
+
+    /// The process can depend on anything, including its own code, which
+    /// the context doesn't capture
+    fn cacheable(&self) -> bool {
+        false
+    }
+}
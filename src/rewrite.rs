@@ -0,0 +1,154 @@
+//! Rewriting of the final HTML of generated pages
+//!
+//! This runs after the page has been put into its template, so it sees
+//! everything on the page, unlike [`crate::Transform`]s which only see the
+//! markdown. Rewriters pick the elements they want with a CSS selector, and
+//! the page is streamed through all of them in one pass.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::path::Path;
+use lol_html::{ElementContentHandlers, HandlerResult, RewriteStrSettings};
+use lol_html::Selector;
+use lol_html::html_content::{ContentType, Element};
+use crate::{Error, Result};
+
+/// A rewriter of elements in generated pages. Rewriters are registered by
+/// name with [`crate::WebsiteBuilder::register_rewriter`], and
+/// `config.rewriters` picks which ones run and in what order.
+///
+/// The built-in rewriters are:
+///
+/// - `lazy_images`: adds `loading="lazy"` to images which don't say how they
+///   should load
+/// - `wrap_tables`: wraps tables in a `<div class="table-wrap">` so they can
+///   be scrolled horizontally with CSS
+/// - `heading_anchors`: adds a `<a class="anchor">` linking to each heading
+///   with an id to the end of it
+pub trait Rewriter: Send + Sync {
+    /// CSS selector of the elements to rewrite, eg. `img[src]`
+    fn selector(&self) -> &str;
+
+    /// Rewrite the matching `element` on the page described by `ctx`
+    fn rewrite(&self, element: &mut Element, ctx: &RewriteContext)
+        -> Result<()>;
+}
+
+/// The page being rewritten
+pub struct RewriteContext<'a> {
+    /// Markdown file the page was generated from
+    pub source: &'a Path,
+
+    /// URL of the page, eg. `/blog/blog1.html`
+    pub page_url: &'a str,
+}
+
+/// A rewriter which will run on every page, with its parsed selector
+pub(crate) struct ActiveRewriter {
+    /// Name the rewriter was registered as
+    name: String,
+
+    /// The rewriter's selector
+    selector: Selector,
+
+    /// The rewriter
+    rewriter: Box<dyn Rewriter>,
+}
+
+impl ActiveRewriter {
+    /// Parse the selector of `rewriter`, registered as `name`
+    pub(crate) fn new(name: String, rewriter: Box<dyn Rewriter>)
+            -> Result<Self> {
+        let selector = rewriter.selector().parse()
+            .map_err(|x| Error::BadSelector(name.clone(), x))?;
+        Ok(ActiveRewriter { name, selector, rewriter })
+    }
+}
+
+/// Run the page `html` described by `ctx` through the `rewriters`
+pub(crate) fn rewrite(rewriters: &[ActiveRewriter], html: &str,
+        ctx: &RewriteContext) -> Result<String> {
+    if rewriters.is_empty() {
+        return Ok(html.to_string());
+    }
+
+    // lol_html only reports that a handler failed, so hold on to the error
+    // so we can return it
+    let failure = RefCell::new(None);
+
+    let handlers = rewriters.iter().map(|active| {
+        let failure = &failure;
+        (Cow::Borrowed(&active.selector),
+            ElementContentHandlers::default().element(
+                    move |element: &mut Element| -> HandlerResult {
+                active.rewriter.rewrite(element, ctx).map_err(|x| {
+                    let message = format!("rewriter `{}` failed", active.name);
+                    *failure.borrow_mut() = Some(x);
+                    message.into()
+                })
+            }))
+    }).collect();
+
+    let ret = lol_html::rewrite_str(html, RewriteStrSettings {
+        element_content_handlers: handlers,
+        ..RewriteStrSettings::new()
+    });
+    match (ret, failure.into_inner()) {
+        (Ok(html), _) => Ok(html),
+        (Err(_), Some(failure)) => Err(failure),
+        (Err(x), None) =>
+            Err(Error::RewriteHtml(ctx.source.to_path_buf(), x.to_string())),
+    }
+}
+
+/// Adds `loading="lazy"` to images which don't say how they should load
+pub(crate) struct LazyImages;
+
+impl Rewriter for LazyImages {
+    fn selector(&self) -> &str {
+        "img:not([loading])"
+    }
+
+    fn rewrite(&self, element: &mut Element, _ctx: &RewriteContext)
+            -> Result<()> {
+        element.set_attribute("loading", "lazy").unwrap();
+        Ok(())
+    }
+}
+
+/// Wraps tables in a `<div class="table-wrap">`, so they can be scrolled
+/// horizontally rather than overflowing the page
+pub(crate) struct WrapTables;
+
+impl Rewriter for WrapTables {
+    fn selector(&self) -> &str {
+        "table"
+    }
+
+    fn rewrite(&self, element: &mut Element, _ctx: &RewriteContext)
+            -> Result<()> {
+        element.before("<div class=\"table-wrap\">", ContentType::Html);
+        element.after("</div>", ContentType::Html);
+        Ok(())
+    }
+}
+
+/// Adds a `#` link to each heading which has an id, so it's easy to link to
+pub(crate) struct HeadingAnchors;
+
+impl Rewriter for HeadingAnchors {
+    fn selector(&self) -> &str {
+        "h1[id], h2[id], h3[id], h4[id], h5[id], h6[id]"
+    }
+
+    fn rewrite(&self, element: &mut Element, _ctx: &RewriteContext)
+            -> Result<()> {
+        let id = element.get_attribute("id").unwrap_or_default();
+        let mut href = String::new();
+        pulldown_cmark::escape::escape_href(&mut href, &id).unwrap();
+        element.append(
+            &format!("<a class=\"anchor\" href=\"#{}\">#</a>", href),
+            ContentType::Html);
+        Ok(())
+    }
+}
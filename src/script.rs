@@ -0,0 +1,342 @@
+//! Handlers implemented by Rhai scripts
+//!
+//! Every `<name>.rhai` in the `handlers` directory of `config.content_path`
+//! handles `cuddly_<name>` code blocks, and whatever the script evaluates to
+//! replaces the block. Scripts get these variables:
+//!
+//! - `input`: the contents of the block, or the arguments of a shortcode
+//! - `config`: the block parsed as TOML, or `()` if it isn't TOML
+//! - `source`, `page_url`: where the block is
+//! - `inline`: whether this is an inline shortcode
+//! - `page`: the page's `templateinfo`, or `()` if it has none
+//!
+//! And these functions:
+//!
+//! - `load_asset(path)`: embed an asset, like the `header` handler does
+//! - `process_md(path)`: render markdown, like the `include` handler does
+//! - `highlight(code, lang)`: highlight code with the site's syntaxes
+//! - `pages(dir)`: metadata and `url` of the posts in `dir`, like the
+//!   `index` handler lists
+//! - `collection(name)`, `collection(name, query)`: metadata and `url` of
+//!   the pages in a collection, where the query is a map like the `index`
+//!   handler takes, eg. `#{ limit: 5, when: "upcoming" }`
+//! - `data(key)`: a value from the data files, eg. `data("talks.0.title")`,
+//!   or `()` if there isn't one
+//! - `markdown(text)`: mark the output as markdown rather than HTML, returning
+//!   `text`
+//! - `warn(message)`: emit a warning about the block
+//!
+//! Paths are relative to `config.content_path`.
+
+use std::sync::{Arc, Mutex};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use rhai::{Dynamic, Engine, EvalAltResult, Scope, AST};
+use crate::{Error, Result, Website, Handler, HandlerContext, TemplateInfo};
+use crate::CollectionQuery;
+use crate::handlers;
+
+/// Number of operations a script may perform per block before it's assumed
+/// to be stuck
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+/// Result type for functions exposed to scripts
+type ScriptResult<T> = std::result::Result<T, Box<EvalAltResult>>;
+
+/// Where a script's block is, and what the script reported back. This is an
+/// owned version of a [`HandlerContext`], as the functions exposed to scripts
+/// can't borrow anything
+struct ScriptState {
+    /// The website being generated
+    website: Arc<Website>,
+
+    /// Markdown file which contains the block
+    source: PathBuf,
+
+    /// URL of the page being generated
+    page_url: String,
+
+    /// Metadata of the page being generated
+    page: Option<TemplateInfo>,
+
+    /// Lines of `source` which the block spans
+    lines: RangeInclusive<usize>,
+
+    /// Whether the block is an inline shortcode
+    inline: bool,
+
+    /// Canonical paths of the markdown being rendered, outermost first
+    include_stack: Vec<PathBuf>,
+
+    /// What the script reported
+    reports: Reports,
+}
+
+/// Everything a script reported, to be applied to the real [`HandlerContext`]
+#[derive(Default)]
+struct Reports {
+    /// Warnings about the block
+    warnings: Vec<String>,
+
+    /// Files the output depends on
+    dependencies: Vec<PathBuf>,
+
+    /// Markdown files which need to be generated into pages
+    pages: Vec<PathBuf>,
+
+    /// Files which need to be copied to the output directory
+    assets: Vec<PathBuf>,
+
+    /// Extra files to write, relative to `config.output_path`
+    outputs: Vec<(PathBuf, Vec<u8>)>,
+
+    /// Whether the script returned markdown
+    markdown: bool,
+}
+
+impl ScriptState {
+    /// Run `func` with a [`HandlerContext`] for the block, collecting what
+    /// gets reported to it
+    fn with_ctx<T>(&mut self,
+            func: impl FnOnce(&Arc<Website>, &mut HandlerContext) -> Result<T>)
+            -> ScriptResult<T> {
+        let mut ctx = HandlerContext::new(&self.source, &self.page_url,
+            self.page.as_ref(), self.lines.clone(), self.inline,
+            &self.include_stack);
+        let ret = func(&self.website, &mut ctx);
+
+        self.reports.warnings.extend(ctx.warnings);
+        self.reports.dependencies.extend(ctx.dependencies);
+        self.reports.pages.extend(ctx.pages);
+        self.reports.assets.extend(ctx.assets);
+        self.reports.outputs.extend(ctx.outputs);
+        ret.map_err(|x| format!("{:?}", x).into())
+    }
+}
+
+/// Convert the URL and metadata of posts into maps for scripts, with the URL
+/// as `url`
+fn posts_to_array(posts: Vec<(String, TemplateInfo)>)
+        -> ScriptResult<rhai::Array> {
+    posts.into_iter().map(|(url, template_info)| {
+        let mut post = rhai::serde::to_dynamic(template_info)?
+            .try_cast::<rhai::Map>().unwrap_or_default();
+        post.insert("url".into(), url.into());
+        Ok(post.into())
+    }).collect()
+}
+
+/// A handler which runs blocks through a Rhai script
+pub struct ScriptHandler {
+    /// Name of the handler, used for error reporting
+    name: String,
+
+    /// The compiled script
+    ast: Arc<AST>,
+}
+
+impl ScriptHandler {
+    /// Compile the `script` into a handler named `name`
+    pub fn new(name: impl Into<String>, script: &str)
+            -> std::result::Result<Self, rhai::ParseError> {
+        Ok(ScriptHandler {
+            name: name.into(),
+            ast:  Arc::new(Engine::new().compile(script)?),
+        })
+    }
+
+    /// Create an engine with the functions scripts can use, which operate on
+    /// `state`. This must be used off of the async threads, as the functions
+    /// block on the runtime behind `runtime`
+    fn engine(state: &Arc<Mutex<ScriptState>>,
+            runtime: &tokio::runtime::Handle) -> Engine {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        let (st, rt) = (state.clone(), runtime.clone());
+        engine.register_fn("load_asset", move |path: &str|
+                -> ScriptResult<String> {
+            st.lock().unwrap().with_ctx(|website, _|
+                rt.block_on(website.load_asset(path)))
+        });
+
+        let (st, rt) = (state.clone(), runtime.clone());
+        engine.register_fn("process_md", move |path: &str|
+                -> ScriptResult<String> {
+            st.lock().unwrap().with_ctx(|website, ctx| rt.block_on(async {
+                let (path, canon_path) =
+                    handlers::resolve_include(website, Path::new(path))
+                    .await?;
+                ctx.add_dependency(&path);
+                handlers::render_include(website, ctx, path, canon_path)
+                    .await
+            }))
+        });
+
+        let st = state.clone();
+        engine.register_fn("highlight", move |code: &str, lang: &str| {
+            st.lock().unwrap().website.highlight_code(code, Some(lang))
+        });
+
+        let (st, rt) = (state.clone(), runtime.clone());
+        engine.register_fn("pages", move |dir: &str|
+                -> ScriptResult<rhai::Array> {
+            let posts = st.lock().unwrap().with_ctx(|website, ctx|
+                rt.block_on(handlers::list_posts(website, ctx,
+                    &website.config.content_path.join(dir))))?;
+            posts_to_array(posts)
+        });
+
+        let (st, rt) = (state.clone(), runtime.clone());
+        engine.register_fn("collection", move |name: &str|
+                -> ScriptResult<rhai::Array> {
+            let posts = st.lock().unwrap().with_ctx(|website, ctx|
+                rt.block_on(website.collection(ctx, name,
+                    &CollectionQuery::default())))?;
+            posts_to_array(posts)
+        });
+
+        let (st, rt) = (state.clone(), runtime.clone());
+        engine.register_fn("collection", move |name: &str, query: rhai::Map|
+                -> ScriptResult<rhai::Array> {
+            let query = rhai::serde::from_dynamic::<CollectionQuery>(
+                &query.into())?;
+            let posts = st.lock().unwrap().with_ctx(|website, ctx|
+                rt.block_on(website.collection(ctx, name, &query)))?;
+            posts_to_array(posts)
+        });
+
+        let st = state.clone();
+        engine.register_fn("data", move |key: &str| -> ScriptResult<Dynamic> {
+            let value = st.lock().unwrap().with_ctx(|website, ctx|
+                Ok(website.data(ctx, key).cloned()))?;
+            Ok(value.map(rhai::serde::to_dynamic).transpose()?
+                .unwrap_or(Dynamic::UNIT))
+        });
+
+        let st = state.clone();
+        engine.register_fn("markdown", move |markdown: &str| {
+            st.lock().unwrap().reports.markdown = true;
+            markdown.to_string()
+        });
+
+        let st = state.clone();
+        engine.register_fn("warn", move |message: &str| {
+            st.lock().unwrap().reports.warnings.push(message.into());
+        });
+
+        engine
+    }
+
+    /// Run the script `ast` on the block `input`, returning the HTML
+    fn run(ast: &AST, input: &str, state: &Arc<Mutex<ScriptState>>,
+            runtime: &tokio::runtime::Handle) -> ScriptResult<String> {
+        let engine = Self::engine(state, runtime);
+
+        let mut scope = Scope::new();
+        {
+            let state = state.lock().unwrap();
+            let config = toml::from_str::<toml::Value>(input).ok()
+                .map(rhai::serde::to_dynamic).transpose()?
+                .unwrap_or(Dynamic::UNIT);
+            scope.push("input", input.to_string());
+            scope.push("config", config);
+            scope.push("source", state.source.display().to_string());
+            scope.push("page_url", state.page_url.clone());
+            scope.push("inline", state.inline);
+            scope.push("page", state.page.as_ref()
+                .map(rhai::serde::to_dynamic).transpose()?
+                .unwrap_or(Dynamic::UNIT));
+        }
+
+        let html = engine.eval_ast_with_scope::<Dynamic>(&mut scope,
+            ast)?;
+        Ok(if html.is_unit() { String::new() } else { html.to_string() })
+    }
+}
+
+#[async_trait]
+impl Handler for ScriptHandler {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let state = Arc::new(Mutex::new(ScriptState {
+            website:       website.clone(),
+            source:        ctx.source.to_path_buf(),
+            page_url:      ctx.page_url.to_string(),
+            page:          ctx.page.cloned(),
+            lines:         ctx.lines.clone(),
+            inline:        ctx.inline,
+            include_stack: ctx.include_stack().to_vec(),
+            reports:       Reports::default(),
+        }));
+
+        // Scripts call back into async code, so they run on their own thread
+        // where they can block on it
+        let runtime = tokio::runtime::Handle::current();
+        let ast = self.ast.clone();
+        let input = input.to_string();
+        let st = state.clone();
+        let html = tokio::task::spawn_blocking(move ||
+                Self::run(&ast, &input, &st, &runtime)
+                    .map_err(|x| x.to_string()))
+            .await
+            .map_err(|x| Error::PluginFailed(self.name.clone(), x.to_string()))?
+            .map_err(|x| Error::PluginFailed(self.name.clone(), x))?;
+
+        // Hand what the script reported to the real context
+        let reports = std::mem::take(&mut state.lock().unwrap().reports);
+        for warning in reports.warnings {
+            ctx.warn(warning);
+        }
+        for dependency in reports.dependencies {
+            ctx.add_dependency(dependency);
+        }
+        for page in reports.pages {
+            ctx.add_page(page);
+        }
+        for asset in reports.assets {
+            ctx.add_asset(asset);
+        }
+        for (path, contents) in reports.outputs {
+            ctx.add_output(path, contents);
+        }
+        if reports.markdown {
+            ctx.return_markdown();
+        }
+
+        Ok(html)
+    }
+
+    /// Scripts can load assets and render markdown, which affect the website
+    fn cacheable(&self) -> bool {
+        false
+    }
+}
+
+/// Load all scripts from `path` as `(handler name, script file, handler)`
+pub(crate) fn load_scripts(path: &Path)
+        -> Result<Vec<(String, PathBuf, ScriptHandler)>> {
+    let mut scripts = Vec::new();
+    if !path.is_dir() {
+        return Ok(scripts);
+    }
+
+    for dirent in std::fs::read_dir(path)
+            .map_err(|x| Error::ReadDirectory(path.to_path_buf(), x))? {
+        let script = dirent
+            .map_err(|x| Error::ReadDirectory(path.to_path_buf(), x))?.path();
+        if script.extension().and_then(|x| x.to_str()) != Some("rhai") {
+            continue;
+        }
+
+        let name = script.file_stem().unwrap().to_string_lossy().into_owned();
+        let source = std::fs::read_to_string(&script)
+            .map_err(|x| Error::ReadScript(script.clone(), x))?;
+        let handler = ScriptHandler::new(name.clone(), &source)
+            .map_err(|x| Error::ParseScript(script.clone(), x))?;
+        scripts.push((name, script, handler));
+    }
+
+    Ok(scripts)
+}
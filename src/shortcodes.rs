@@ -0,0 +1,134 @@
+//! Inline shortcodes, eg. `{{ icon "github" }}` or `{% post_link blog1 %}`
+//!
+//! Both forms are equivalent. The first word names the handler, and the rest
+//! is handed to it as the input, with [`crate::HandlerContext::inline`] set.
+//! Shortcodes which don't name a handler are left as they are, with a
+//! warning, as braces turn up in prose, eg. quoted Jinja templates.
+
+/// A piece of text which may contain shortcodes
+#[derive(Debug, PartialEq, Eq)]
+pub enum Segment<'a> {
+    /// Plain text
+    Text(&'a str),
+
+    /// A shortcode invoking the handler `name` with `args`, written as
+    /// `text`
+    Shortcode { name: &'a str, args: &'a str, text: &'a str },
+}
+
+/// Split `text` into plain text and shortcodes. Anything which doesn't look
+/// like a complete shortcode is left as text
+pub fn parse(text: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = text;
+    let mut literal = 0;
+
+    while let Some(start) = rest[literal..].find('{').map(|x| x + literal) {
+        let close = match rest.get(start..start + 2) {
+            Some("{{") => "}}",
+            Some("{%") => "%}",
+            _ => {
+                literal = start + 1;
+                continue;
+            }
+        };
+
+        // Find the end of the shortcode and the handler it names
+        let shortcode = rest[start + 2..].find(close).map(|end| {
+            let inner = rest[start + 2..start + 2 + end].trim();
+            let name_len = inner.find(char::is_whitespace)
+                .unwrap_or(inner.len());
+            (start + 2 + end + 2, &inner[..name_len], inner[name_len..].trim())
+        });
+        match shortcode {
+            Some((end, name, args)) if !name.is_empty() &&
+                    name.chars().all(|x| x.is_alphanumeric() || x == '_') => {
+                if start > 0 {
+                    segments.push(Segment::Text(&rest[..start]));
+                }
+                let text = &rest[start..end];
+                segments.push(Segment::Shortcode { name, args, text });
+                rest = &rest[end..];
+                literal = 0;
+            }
+            _ => literal = start + 1,
+        }
+    }
+
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+    segments
+}
+
+/// Split shortcode arguments on whitespace, where double-quoted arguments
+/// may contain whitespace, eg. `"Ctrl Alt" Del` is `["Ctrl Alt", "Del"]`
+pub fn split_args(args: &str) -> Vec<String> {
+    let mut ret = Vec::new();
+    let mut chars = args.trim().chars().peekable();
+
+    while let Some(&chr) = chars.peek() {
+        if chr.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut arg = String::new();
+        if chr == '"' {
+            chars.next();
+            for chr in chars.by_ref() {
+                if chr == '"' {
+                    break;
+                }
+                arg.push(chr);
+            }
+        } else {
+            while let Some(chr) = chars.next_if(|x| !x.is_whitespace()) {
+                arg.push(chr);
+            }
+        }
+        ret.push(arg);
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, split_args, Segment};
+
+    #[test]
+    fn both_forms() {
+        assert_eq!(parse("a {{ icon github }} b {% kbd Ctrl C %}"), [
+            Segment::Text("a "),
+            Segment::Shortcode { name: "icon", args: "github",
+                text: "{{ icon github }}" },
+            Segment::Text(" b "),
+            Segment::Shortcode { name: "kbd", args: "Ctrl C",
+                text: "{% kbd Ctrl C %}" },
+        ]);
+    }
+
+    #[test]
+    fn incomplete_shortcodes_are_text() {
+        for text in ["{{ icon", "{ icon }", "{{ }}", "{{ a-b }}", "{% x }}"] {
+            assert_eq!(parse(text), [Segment::Text(text)]);
+        }
+    }
+
+    #[test]
+    fn text_around_braces() {
+        assert_eq!(parse("{x} {{date}}{"), [
+            Segment::Text("{x} "),
+            Segment::Shortcode { name: "date", args: "", text: "{{date}}" },
+            Segment::Text("{"),
+        ]);
+    }
+
+    #[test]
+    fn quoted_args() {
+        assert_eq!(split_args(r#" "Ctrl Alt"  Del "" x"#),
+            ["Ctrl Alt", "Del", "", "x"]);
+        assert!(split_args("  ").is_empty());
+    }
+}
@@ -0,0 +1,322 @@
+//! Sanitizing and inlining of SVG assets
+//!
+//! SVGs are stripped down to a whitelist of elements and attributes, recolored
+//! to `currentColor` so CSS can style them, and turned into a `<symbol>` which
+//! pages reference with `<use>`. Each page only gets the symbols it uses.
+//!
+//! Symbols share a sprite, so ids inside an SVG are prefixed with its symbol
+//! id, and references may only point at those ids.
+
+use std::path::Path;
+use std::collections::BTreeMap;
+use pulldown_cmark::escape::escape_html;
+use crate::{Error, Result};
+
+/// Elements which are allowed to survive sanitization. Anything else (eg.
+/// `<script>` or `<foreignObject>`) is dropped along with all its children
+const ALLOWED_ELEMENTS: &[&str] = &[
+    "g", "path", "circle", "ellipse", "line", "polyline", "polygon", "rect",
+    "defs", "linearGradient", "radialGradient", "stop", "clipPath", "mask",
+    "text", "tspan", "use",
+];
+
+/// Attributes which are allowed to survive sanitization
+const ALLOWED_ATTRIBUTES: &[&str] = &[
+    "d", "cx", "cy", "r", "rx", "ry", "x", "y", "x1", "y1", "x2", "y2",
+    "width", "height", "points", "transform", "fill", "fill-rule",
+    "fill-opacity", "stroke", "stroke-width", "stroke-linecap",
+    "stroke-linejoin", "stroke-miterlimit", "stroke-dasharray",
+    "stroke-dashoffset", "stroke-opacity", "opacity", "clip-path",
+    "clip-rule", "mask", "offset", "stop-color", "stop-opacity",
+    "gradientUnits", "gradientTransform", "spreadMethod", "fx", "fy",
+    "font-size", "font-family", "font-weight", "text-anchor", "href",
+    "id", "class",
+];
+
+/// Attributes of the root `<svg>` which get moved onto the `<symbol>`
+const SYMBOL_ATTRIBUTES: &[&str] = &[
+    "viewBox", "fill", "fill-rule", "stroke", "stroke-width",
+    "stroke-linecap", "stroke-linejoin", "stroke-miterlimit",
+];
+
+/// A sanitized SVG, ready to be placed in a sprite
+#[derive(Debug)]
+pub struct Symbol {
+    /// The `<symbol id="...">` which `<use>` references
+    pub id: String,
+
+    /// The full `<symbol>...</symbol>` markup
+    pub markup: String,
+
+    /// Width of the original SVG, used as the default rendered width
+    pub width: String,
+
+    /// Height of the original SVG, used as the default rendered height
+    pub height: String,
+}
+
+impl Symbol {
+    /// Sanitize the SVG `contents` loaded from `path` into a symbol named `id`
+    pub fn parse(path: &Path, id: String, contents: &str) -> Result<Self> {
+        let doc = roxmltree::Document::parse(contents)
+            .map_err(|x| Error::ParseSvg(path.to_path_buf(), x))?;
+        let root = doc.root_element();
+        if root.tag_name().name() != "svg" {
+            return Err(Error::NotSvg(path.to_path_buf()));
+        }
+
+        // Default to 24x24 which is what most icon sets use
+        let width  = root.attribute("width").unwrap_or("24").to_string();
+        let height = root.attribute("height").unwrap_or("24").to_string();
+
+        let mut markup = String::new();
+        markup += "<symbol id=\"";
+        markup += &id;
+        markup += "\"";
+        for &attr in SYMBOL_ATTRIBUTES {
+            if let Some(value) = root.attribute(attr)
+                    .and_then(|x| sanitize_value(&id, attr, x)) {
+                push_attribute(&mut markup, attr, &value);
+            }
+        }
+
+        // If there is no `viewBox` we make one from the size so the symbol
+        // still scales when rendered at a different size
+        if root.attribute("viewBox").is_none() {
+            push_attribute(&mut markup, "viewBox",
+                &format!("0 0 {} {}", width, height));
+        }
+        markup += ">";
+
+        for child in root.children() {
+            sanitize_node(&mut markup, &id, child);
+        }
+        markup += "</symbol>";
+
+        Ok(Symbol { id, markup, width, height })
+    }
+
+    /// Create the inline `<svg>` which renders this symbol, with an accessible
+    /// `title`
+    pub fn reference(&self, title: &str) -> String {
+        let mut buf = String::new();
+        buf += "<svg class=\"icon\" role=\"img\"";
+        push_attribute(&mut buf, "width", &self.width);
+        push_attribute(&mut buf, "height", &self.height);
+        buf += "><title>";
+        escape_html(&mut buf, title).unwrap();
+        buf += "</title><use href=\"#";
+        buf += &self.id;
+        buf += "\" /></svg>";
+        buf
+    }
+}
+
+/// Create a symbol id from an asset path, eg. `feather/github.svg` becomes
+/// `svg-feather-github-` followed by a hash of the path, as paths like
+/// `a/b-c.svg` and `a-b/c.svg` read the same
+pub fn symbol_id(path: &Path) -> String {
+    let mut id = String::from("svg-");
+    for chr in path.with_extension("").to_string_lossy().chars() {
+        if chr.is_ascii_alphanumeric() || chr == '_' {
+            id.push(chr.to_ascii_lowercase());
+        } else if !id.ends_with('-') {
+            id.push('-');
+        }
+    }
+    if !id.ends_with('-') {
+        id.push('-');
+    }
+    id += &blake3::hash(path.to_string_lossy().as_bytes()).to_hex()[..8];
+    id
+}
+
+/// Build the hidden sprite containing every symbol in `symbols` that is
+/// referenced by `html`
+pub fn sprite(symbols: &BTreeMap<String, Symbol>, html: &str) -> String {
+    let mut sprite = String::new();
+    for (id, symbol) in symbols {
+        if html.contains(&format!("href=\"#{}\"", id)) {
+            sprite += &symbol.markup;
+        }
+    }
+
+    if sprite.is_empty() {
+        sprite
+    } else {
+        format!("<svg xmlns=\"http://www.w3.org/2000/svg\" \
+            style=\"display:none\">{}</svg>", sprite)
+    }
+}
+
+/// Get the value of the attribute `name` of the symbol `id`, with ids and
+/// references to them prefixed by `id`. Returns `None` for references to
+/// anything outside the symbol
+fn sanitize_value(id: &str, name: &str, value: &str) -> Option<String> {
+    if name == "id" {
+        Some(format!("{}-{}", id, value))
+    } else if name == "href" {
+        value.strip_prefix('#').map(|x| format!("#{}-{}", id, x))
+    } else if value.contains("url(") {
+        let target = value.trim().strip_prefix("url(#")?.strip_suffix(')')?;
+        (!target.contains(['(', ')'])).then(||
+            format!("url(#{}-{})", id, target))
+    } else {
+        Some(value.to_string())
+    }
+}
+
+/// Append ` name="value"` to `buf`, escaping the value
+fn push_attribute(buf: &mut String, name: &str, value: &str) {
+    // Recolor everything to `currentColor` so CSS controls the color
+    let value = match name {
+        "fill" | "stroke" | "stop-color"
+            if value != "none" && !value.starts_with("url(") =>
+            "currentColor",
+        _ => value,
+    };
+
+    *buf += " ";
+    *buf += name;
+    *buf += "=\"";
+    escape_html(&mut *buf, value).unwrap();
+    *buf += "\"";
+}
+
+/// Append the sanitized form of `node` and its children, in the symbol `id`,
+/// to `buf`
+fn sanitize_node(buf: &mut String, id: &str, node: roxmltree::Node) {
+    if node.is_text() {
+        escape_html(&mut *buf, node.text().unwrap_or("")).unwrap();
+        return;
+    }
+
+    let name = node.tag_name().name();
+    if !node.is_element() || !ALLOWED_ELEMENTS.contains(&name) {
+        return;
+    }
+
+    *buf += "<";
+    *buf += name;
+    for attr in node.attributes() {
+        if !ALLOWED_ATTRIBUTES.contains(&attr.name()) {
+            continue;
+        }
+        if let Some(value) = sanitize_value(id, attr.name(), attr.value()) {
+            push_attribute(buf, attr.name(), &value);
+        }
+    }
+    *buf += ">";
+
+    for child in node.children() {
+        sanitize_node(buf, id, child);
+    }
+
+    *buf += "</";
+    *buf += name;
+    *buf += ">";
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::collections::BTreeMap;
+    use super::{Symbol, symbol_id, sprite};
+
+    /// Sanitize `svg` into a symbol named `icon`
+    fn parse(svg: &str) -> Symbol {
+        Symbol::parse(Path::new("icon.svg"), "icon".into(), svg).unwrap()
+    }
+
+    #[test]
+    fn drops_scripts_and_foreign_content() {
+        let symbol = parse(r#"<svg xmlns="http://www.w3.org/2000/svg">
+            <script>alert(1)</script>
+            <foreignObject><p>hi</p></foreignObject>
+            <path d="M0 0" onclick="alert(1)"/></svg>"#);
+        assert!(!symbol.markup.contains("script"));
+        assert!(!symbol.markup.contains("foreignObject"));
+        assert!(!symbol.markup.contains("onclick"));
+        assert!(symbol.markup.contains("<path d=\"M0 0\"></path>"));
+    }
+
+    #[test]
+    fn only_local_references() {
+        let symbol = parse(r##"<svg xmlns="http://www.w3.org/2000/svg"
+                xmlns:xlink="http://www.w3.org/1999/xlink">
+            <use href="https://example.com/x.svg#a"/>
+            <use xlink:href="https://example.com/x.svg#a"/>
+            <rect fill="url(https://example.com/x.svg#a)"
+                mask="url(#a) url(//example.com)" clip-path="url(#a)"/>
+            <use href="#b"/></svg>"##);
+        assert!(!symbol.markup.contains("example.com"));
+        assert_eq!(symbol.markup.matches("<use></use>").count(), 2);
+        assert!(symbol.markup.contains(
+            "<rect clip-path=\"url(#icon-a)\"></rect>"));
+        assert!(symbol.markup.contains("<use href=\"#icon-b\"></use>"));
+    }
+
+    #[test]
+    fn ids_are_namespaced() {
+        // Two icons using the same gradient id keep their own gradients
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg">
+            <linearGradient id="a"/><rect fill="url(#a)"/></svg>"##;
+        let one = Symbol::parse(Path::new("1.svg"), "one".into(), svg)
+            .unwrap();
+        let two = Symbol::parse(Path::new("2.svg"), "two".into(), svg)
+            .unwrap();
+        assert!(one.markup.contains("<linearGradient id=\"one-a\">\
+            </linearGradient><rect fill=\"url(#one-a)\"></rect>"));
+        assert!(two.markup.contains("<linearGradient id=\"two-a\">\
+            </linearGradient><rect fill=\"url(#two-a)\"></rect>"));
+    }
+
+    #[test]
+    fn recolors_and_sizes() {
+        let symbol = parse(r#"<svg xmlns="http://www.w3.org/2000/svg"
+            width="16" height="8" fill="none" stroke="red">
+            <rect fill="url(#g)" stroke="blue"/></svg>"#);
+        assert_eq!((symbol.width.as_str(), symbol.height.as_str()),
+            ("16", "8"));
+        assert!(symbol.markup.starts_with("<symbol id=\"icon\" fill=\"none\" \
+            stroke=\"currentColor\" viewBox=\"0 0 16 8\">"));
+        assert!(symbol.markup.contains(
+            "<rect fill=\"url(#icon-g)\" stroke=\"currentColor\"></rect>"));
+    }
+
+    #[test]
+    fn escapes_text() {
+        let symbol = parse(r#"<svg xmlns="http://www.w3.org/2000/svg">
+            <text>&lt;b&gt; &amp; "q"</text></svg>"#);
+        assert!(symbol.markup.contains(
+            "<text>&lt;b&gt; &amp; &quot;q&quot;</text>"));
+    }
+
+    #[test]
+    fn rejects_other_documents() {
+        assert!(Symbol::parse(Path::new("x.svg"), "x".into(), "<html/>")
+            .is_err());
+        assert!(Symbol::parse(Path::new("x.svg"), "x".into(), "<svg><g></svg>")
+            .is_err());
+    }
+
+    #[test]
+    fn ids_and_sprites() {
+        let id = symbol_id(Path::new("feather/git-hub.svg"));
+        assert!(id.starts_with("svg-feather-git-hub-"));
+        assert_eq!(id, symbol_id(Path::new("feather/git-hub.svg")));
+
+        // Paths which read the same still get their own symbols
+        assert_ne!(symbol_id(Path::new("a/b-c.svg")),
+            symbol_id(Path::new("a-b/c.svg")));
+
+        let mut symbols = BTreeMap::new();
+        symbols.insert("icon".to_string(),
+            parse(r#"<svg xmlns="http://www.w3.org/2000/svg"/>"#));
+        assert_eq!(sprite(&symbols, "<p>nothing</p>"), "");
+        assert!(sprite(&symbols, &symbols["icon"].reference("<x>"))
+            .contains("<symbol id=\"icon\""));
+        assert!(symbols["icon"].reference("<x>")
+            .contains("<title>&lt;x&gt;</title>"));
+    }
+}
@@ -0,0 +1,362 @@
+//! Tables of data loaded from CSV files
+
+use std::sync::Arc;
+use std::cmp::Ordering;
+use std::path::PathBuf;
+use std::collections::HashMap;
+use async_trait::async_trait;
+use serde_derive::Deserialize;
+use pulldown_cmark::escape::escape_html;
+use crate::{Error, Result, Website, Handler, HandlerContext};
+use crate::handlers::{handler_template, parse_config, load_csv};
+
+/// Renders a CSV or TSV file into a table, eg.
+///
+/// ```toml
+/// file = "data/bench.csv"
+/// caption = "Fuzz cases per second"
+/// columns = ["fuzzer", "cores", "fcps"]
+/// sort = "fcps"
+/// descending = true
+///
+/// [rename]
+/// fcps = "Cases/sec"
+///
+/// [format]
+/// fcps = { decimals = 1, separators = true }
+///
+/// [[highlight]]
+/// column = "fcps"
+/// best = "max"
+/// ```
+///
+/// Columns which are all numbers are aligned right, others to the left
+#[derive(Default)]
+pub(crate) struct Table;
+
+#[derive(Debug, Deserialize)]
+struct TableConfig {
+    /// CSV or TSV file with the data, relative to `config.content_path`
+    file: PathBuf,
+
+    /// Character separating the cells, if the file's extension doesn't say
+    delimiter: Option<char>,
+
+    /// Caption of the table, which describes it to screen readers
+    caption: Option<String>,
+
+    /// Columns to show, by their name in the file, in order. Every column is
+    /// shown if this isn't given
+    columns: Option<Vec<String>>,
+
+    /// Names to show for columns, by their name in the file
+    #[serde(default)]
+    rename: HashMap<String, String>,
+
+    /// How to format the numbers in columns, by their name in the file
+    #[serde(default)]
+    format: HashMap<String, NumberFormat>,
+
+    /// Alignment of columns, by their name in the file
+    #[serde(default)]
+    align: HashMap<String, Align>,
+
+    /// Column to sort the rows by, otherwise they're in the file's order
+    sort: Option<String>,
+
+    /// Sort the rows from largest to smallest
+    #[serde(default)]
+    descending: bool,
+
+    /// Make the first column headers for the rows
+    #[serde(default)]
+    row_headers: bool,
+
+    /// Rules for which cells and rows to highlight
+    #[serde(default)]
+    highlight: Vec<Highlight>,
+}
+
+/// How to format numbers
+#[derive(Debug, Default, Deserialize)]
+struct NumberFormat {
+    /// Number of decimal places, otherwise the number is shown as written
+    decimals: Option<usize>,
+
+    /// Separate thousands with commas
+    #[serde(default)]
+    separators: bool,
+
+    /// Text to put before the number, eg. `$`
+    #[serde(default)]
+    prefix: String,
+
+    /// Text to put after the number, eg. `%`
+    #[serde(default)]
+    suffix: String,
+}
+
+impl NumberFormat {
+    /// Format the `cell`, if it's a number
+    fn apply(&self, cell: &str) -> String {
+        let Ok(number) = cell.trim().parse::<f64>() else {
+            return cell.to_string();
+        };
+
+        let mut formatted = match self.decimals {
+            Some(decimals) => format!("{:.*}", decimals, number),
+            None => cell.trim().to_string(),
+        };
+        if self.separators {
+            formatted = separate_thousands(&formatted);
+        }
+        format!("{}{}{}", self.prefix, formatted, self.suffix)
+    }
+}
+
+/// Put commas between the thousands of the integer part of `number`
+fn separate_thousands(number: &str) -> String {
+    let (sign, number) = match number.strip_prefix('-') {
+        Some(number) => ("-", number),
+        None => ("", number),
+    };
+    let split = number.find(|x: char| !x.is_ascii_digit())
+        .unwrap_or(number.len());
+    let (integer, rest) = number.split_at(split);
+
+    let mut separated = String::new();
+    for (ii, digit) in integer.chars().enumerate() {
+        if ii > 0 && (integer.len() - ii) % 3 == 0 {
+            separated.push(',');
+        }
+        separated.push(digit);
+    }
+    format!("{}{}{}", sign, separated, rest)
+}
+
+/// Alignment of a column
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// A rule for highlighting cells or rows
+#[derive(Debug, Deserialize)]
+struct Highlight {
+    /// Column the rule looks at, by its name in the file
+    column: String,
+
+    /// Make the largest or smallest numbers in the column bold
+    best: Option<Best>,
+
+    /// Highlight the rows where the column has this value
+    equals: Option<String>,
+}
+
+/// Which number in a column is the best
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Best {
+    Max,
+    Min,
+}
+
+/// Escape `text` for use in HTML
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    escape_html(&mut escaped, text).unwrap();
+    escaped
+}
+
+/// Compare cells, with numbers before other text, numbers in numeric order
+/// and text in string order, all reversed if `descending`. Empty cells come
+/// last either way
+fn compare(a: &str, b: &str, descending: bool) -> Ordering {
+    let (a, b) = (a.trim(), b.trim());
+    if a.is_empty() || b.is_empty() {
+        return a.is_empty().cmp(&b.is_empty());
+    }
+
+    let order = match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.total_cmp(&b),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    };
+    if descending { order.reverse() } else { order }
+}
+
+#[async_trait]
+impl Handler for Table {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let config: TableConfig = parse_config(ctx, "table", input)?;
+        let bad_input = |ctx: &HandlerContext, message: String|
+            Error::BadHandlerInput(ctx.source.to_path_buf(), "table".into(),
+                message);
+
+        // Load the data
+        let delimiter = match config.delimiter {
+            Some(x) if x.is_ascii() => Some(x as u8),
+            Some(x) => return Err(bad_input(ctx,
+                format!("`{}` isn't an ASCII delimiter", x))),
+            None => None,
+        };
+        let (header, mut rows) =
+            load_csv(website, ctx, "table", &config.file, delimiter).await?;
+
+        // Find the columns the config talks about
+        let column = |name: &str| header.iter().position(|x| x == name)
+            .ok_or_else(|| bad_input(ctx,
+                format!("there is no column `{}`", name)));
+        let columns = match &config.columns {
+            Some(names) => names.iter().map(|x| column(x))
+                .collect::<Result<Vec<_>>>()?,
+            None => (0..header.len()).collect(),
+        };
+        for name in config.rename.keys().chain(config.format.keys())
+                .chain(config.align.keys())
+                .chain(config.highlight.iter().map(|x| &x.column)) {
+            column(name)?;
+        }
+
+        // Sort the rows, keeping the file's order for equal cells
+        let sort = config.sort.as_deref().map(column).transpose()?;
+        if let Some(sort) = sort {
+            rows.sort_by(|a, b| compare(&a[sort], &b[sort], config.descending));
+        }
+
+        // Find the best number in each highlighted column
+        let mut best = HashMap::new();
+        for rule in &config.highlight {
+            let Some(kind) = rule.best else { continue };
+            let idx = column(&rule.column)?;
+            let numbers = rows.iter()
+                .filter_map(|x| x[idx].trim().parse::<f64>().ok());
+            let value = match kind {
+                Best::Max => numbers.reduce(f64::max),
+                Best::Min => numbers.reduce(f64::min),
+            };
+            if let Some(value) = value {
+                best.insert(idx, value);
+            }
+        }
+
+        // Align each column as configured, or numbers to the right
+        let align = columns.iter().map(|&idx| {
+            config.align.get(&header[idx]).copied().unwrap_or_else(|| {
+                let numeric = rows.iter().map(|x| x[idx].trim())
+                    .filter(|x| !x.is_empty())
+                    .all(|x| x.parse::<f64>().is_ok());
+                if numeric && !rows.is_empty() { Align::Right }
+                else { Align::Left }
+            })
+        }).collect::<Vec<_>>();
+        let style = |align: Align| match align {
+            Align::Left => "",
+            Align::Center => " style=\"text-align:center\"",
+            Align::Right => " style=\"text-align:right\"",
+        };
+
+        // The header, saying which column is sorted
+        let mut head = String::new();
+        for (&idx, &align) in columns.iter().zip(&align) {
+            let name = config.rename.get(&header[idx]).unwrap_or(&header[idx]);
+            let sorted = match sort {
+                Some(sort) if sort == idx && config.descending =>
+                    " aria-sort=\"descending\"",
+                Some(sort) if sort == idx => " aria-sort=\"ascending\"",
+                _ => "",
+            };
+            head += &format!("<th scope=\"col\"{}{}>{}</th>", style(align),
+                sorted, escape(name));
+        }
+
+        // The rows
+        let mut body = String::new();
+        for row in &rows {
+            let highlighted = config.highlight.iter().any(|rule| {
+                let idx = header.iter().position(|x| *x == rule.column);
+                rule.equals.is_some() &&
+                    idx.map(|x| row[x].trim()) == rule.equals.as_deref()
+            });
+            body += if highlighted { "<tr class=\"highlight\">" }
+                else { "<tr>" };
+
+            for (ii, (&idx, &align)) in columns.iter().zip(&align).enumerate() {
+                let mut cell = escape(&match config.format.get(&header[idx]) {
+                    Some(format) => format.apply(&row[idx]),
+                    None => row[idx].clone(),
+                });
+                let is_best = best.get(&idx).is_some_and(|&x| {
+                    row[idx].trim().parse::<f64>().ok() == Some(x)
+                });
+                if is_best || highlighted {
+                    cell = format!("<strong>{}</strong>", cell);
+                }
+
+                if ii == 0 && config.row_headers {
+                    body += &format!("<th scope=\"row\"{}>{}</th>",
+                        style(align), cell);
+                } else {
+                    body += &format!("<td{}>{}</td>", style(align), cell);
+                }
+            }
+            body += "</tr>\n";
+        }
+
+        let caption = config.caption.as_deref()
+            .map(|x| format!("<caption>{}</caption>", escape(x)))
+            .unwrap_or_default();
+        let output = handler_template(website, ctx, "table.html").await?;
+        Ok(output.replace("<<<PUT THE CAPTION HERE>>>", &caption)
+            .replace("<<<PUT THE HEADER HERE>>>", &head)
+            .replace("<<<PUT THE ROWS HERE>>>", &body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::compare;
+
+    /// Sort `cells` the way a table column is sorted
+    fn sorted(cells: &[&str], descending: bool) -> Vec<String> {
+        let mut cells = cells.iter().map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        cells.sort_by(|a, b| compare(a, b, descending));
+        cells
+    }
+
+    #[test]
+    fn numbers_then_text_then_empty() {
+        assert_eq!(sorted(&["10", "9", "1a", "", "2"], false),
+            ["2", "9", "10", "1a", ""]);
+        assert_eq!(sorted(&["b", "-1.5", "", "a", "3e2"], false),
+            ["-1.5", "3e2", "a", "b", ""]);
+    }
+
+    #[test]
+    fn descending_keeps_empty_last() {
+        assert_eq!(sorted(&["10", "9", "1a", "", "2"], true),
+            ["1a", "10", "9", "2", ""]);
+    }
+
+    #[test]
+    fn order_is_transitive() {
+        let cells = ["9", "10", "1a", "", " ", "2", "x", "NaN", "-0"];
+        for a in cells {
+            for b in cells {
+                for c in cells {
+                    if compare(a, b, false).is_le() &&
+                            compare(b, c, false).is_le() {
+                        assert!(compare(a, c, false).is_le(),
+                            "{:?} <= {:?} <= {:?}", a, b, c);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Rendering of terminal output containing ANSI escape sequences
+
+use std::sync::Arc;
+use async_trait::async_trait;
+use vte::{Params, Parser, Perform};
+use crate::{Result, Website, Handler, HandlerContext};
+use crate::handlers::{handler_template, theme_colors};
+
+/// The 8 normal then 8 bright terminal colors
+const PALETTE: [Rgb; 16] = [
+    (0x00, 0x00, 0x00), (0xcd, 0x31, 0x31), (0x0d, 0xbc, 0x79),
+    (0xe5, 0xe5, 0x10), (0x24, 0x72, 0xc8), (0xbc, 0x3f, 0xbc),
+    (0x11, 0xa8, 0xcd), (0xe5, 0xe5, 0xe5), (0x66, 0x66, 0x66),
+    (0xf1, 0x4c, 0x4c), (0x23, 0xd1, 0x8b), (0xf5, 0xf5, 0x43),
+    (0x3b, 0x8e, 0xea), (0xd6, 0x70, 0xd6), (0x29, 0xb8, 0xdb),
+    (0xff, 0xff, 0xff),
+];
+
+/// Ways of writing the escape character in text, for output which was
+/// pasted with its escapes spelled out
+const ESCAPES: &[&str] = &["\\x1b", "\\x1B", "\\033", "\\u001b"];
+
+/// Width of the screen. Characters written past it are dropped, so escapes
+/// can't move the cursor arbitrarily far
+const MAX_COLUMNS: usize = 1024;
+
+/// A color
+type Rgb = (u8, u8, u8);
+
+/// How a character is displayed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Style {
+    /// Text color, or the default
+    fg: Option<Rgb>,
+
+    /// Background color, or the default
+    bg: Option<Rgb>,
+
+    /// Bold text
+    bold: bool,
+
+    /// Faint text
+    dim: bool,
+
+    /// Italic text
+    italic: bool,
+
+    /// Underlined text
+    underline: bool,
+
+    /// Swap the text and background colors
+    inverse: bool,
+}
+
+impl Style {
+    /// Get the CSS for the style, where `fg` and `bg` are the default colors
+    fn css(&self, fg: &str, bg: &str) -> String {
+        let color = |x: Option<Rgb>, default: &str| match x {
+            Some((r, g, b)) => format!("#{:02x}{:02x}{:02x}", r, g, b),
+            None => default.to_string(),
+        };
+        let (mut fg, mut bg) = (color(self.fg, fg), color(self.bg, bg));
+        if self.inverse {
+            std::mem::swap(&mut fg, &mut bg);
+        }
+
+        let mut css = String::new();
+        if self.fg.is_some() || self.inverse {
+            css += &format!("color:{};", fg);
+        }
+        if self.bg.is_some() || self.inverse {
+            css += &format!("background-color:{};", bg);
+        }
+        if self.bold {
+            css += "font-weight:bold;";
+        }
+        if self.dim {
+            css += "opacity:0.7;";
+        }
+        if self.italic {
+            css += "font-style:italic;";
+        }
+        if self.underline {
+            css += "text-decoration:underline;";
+        }
+        css
+    }
+}
+
+/// Get the color `index` of the 256 color palette
+fn color_256(index: u8) -> Rgb {
+    match index {
+        0..=15 => PALETTE[index as usize],
+        16..=231 => {
+            let level = |x: u8| if x == 0 { 0 } else { 55 + x * 40 };
+            let index = index - 16;
+            (level(index / 36), level(index / 6 % 6), level(index % 6))
+        }
+        _ => {
+            let gray = 8 + (index - 232) * 10;
+            (gray, gray, gray)
+        }
+    }
+}
+
+/// Get an extended color from SGR `values` following a 38 or 48, eg.
+/// `5;196` or `2;255;0;0`
+fn extended_color(values: &mut impl Iterator<Item = u16>) -> Option<Rgb> {
+    match values.next()? {
+        5 => Some(color_256(values.next()? as u8)),
+        2 => Some((values.next()? as u8, values.next()? as u8,
+            values.next()? as u8)),
+        _ => None,
+    }
+}
+
+/// The lines written to the terminal. Only movement within a line is
+/// followed, so `\r` progress lines collapse into their last state, and
+/// anything else which moves the cursor is dropped
+#[derive(Default)]
+struct Screen {
+    /// Lines of characters and how they're displayed
+    lines: Vec<Vec<(char, Style)>>,
+
+    /// Column of the cursor in the last line
+    col: usize,
+
+    /// Style of characters being written
+    style: Style,
+}
+
+impl Screen {
+    /// Apply the SGR sequence with `params` to the style
+    fn sgr(&mut self, params: &Params) {
+        if params.is_empty() {
+            self.style = Style::default();
+        }
+
+        let mut params = params.iter();
+        while let Some(param) = params.next() {
+            match param[0] {
+                0 => self.style = Style::default(),
+                1 => self.style.bold = true,
+                2 => self.style.dim = true,
+                3 => self.style.italic = true,
+                4 => self.style.underline = true,
+                7 => self.style.inverse = true,
+                21 | 22 => {
+                    self.style.bold = false;
+                    self.style.dim = false;
+                }
+                23 => self.style.italic = false,
+                24 => self.style.underline = false,
+                27 => self.style.inverse = false,
+                x @ 30..=37 => self.style.fg = Some(PALETTE[x as usize - 30]),
+                39 => self.style.fg = None,
+                x @ 40..=47 => self.style.bg = Some(PALETTE[x as usize - 40]),
+                49 => self.style.bg = None,
+                x @ 90..=97 =>
+                    self.style.fg = Some(PALETTE[x as usize - 90 + 8]),
+                x @ 100..=107 =>
+                    self.style.bg = Some(PALETTE[x as usize - 100 + 8]),
+                x @ (38 | 48) => {
+                    // Either `38;5;196` or `38:5:196`, where truecolor may
+                    // have a color space id, eg. `38:2::255:0:0`
+                    let color = if param.len() > 1 {
+                        let values = match param {
+                            [_, 2, _, r, g, b] => vec![2, *r, *g, *b],
+                            _ => param[1..].to_vec(),
+                        };
+                        extended_color(&mut values.into_iter())
+                    } else {
+                        extended_color(&mut params.by_ref().map(|x| x[0]))
+                    };
+
+                    if x == 38 {
+                        self.style.fg = color;
+                    } else {
+                        self.style.bg = color;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Render the lines into HTML, where `fg` and `bg` are the default colors
+    fn render(&self, fg: &str, bg: &str) -> String {
+        // Output usually ends with newlines which don't need showing
+        let mut lines = &self.lines[..];
+        while let Some((last, rest)) = lines.split_last() {
+            if !last.is_empty() {
+                break;
+            }
+            lines = rest;
+        }
+
+        let mut html = String::new();
+        for line in lines {
+            // Runs of characters with the same style share a span
+            for run in line.chunk_by(|a, b| a.1 == b.1) {
+                let style = run[0].1;
+                if style != Style::default() {
+                    html += &format!("<span style=\"{}\">", style.css(fg, bg));
+                }
+                for &(chr, _) in run {
+                    match chr {
+                        '<' => html += "&lt;",
+                        '>' => html += "&gt;",
+                        '&' => html += "&amp;",
+                        _   => html.push(chr),
+                    }
+                }
+                if style != Style::default() {
+                    html += "</span>";
+                }
+            }
+            html += "\n";
+        }
+        html
+    }
+}
+
+impl Perform for Screen {
+    fn print(&mut self, chr: char) {
+        let (col, style) = (self.col, self.style);
+        if col >= MAX_COLUMNS {
+            return;
+        }
+
+        let line = self.lines.last_mut().unwrap();
+        if line.len() <= col {
+            line.resize(col, (' ', Style::default()));
+            line.push((chr, style));
+        } else {
+            line[col] = (chr, style);
+        }
+        self.col += 1;
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            b'\n' => {
+                self.lines.push(Vec::new());
+                self.col = 0;
+            }
+            b'\r' => self.col = 0,
+            0x08 => self.col = self.col.saturating_sub(1),
+            b'\t' => {
+                for _ in self.col % 8..8 {
+                    self.print(' ');
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8],
+            ignore: bool, action: char) {
+        if ignore || !intermediates.is_empty() {
+            return;
+        }
+
+        let count = params.iter().next().map(|x| x[0]).unwrap_or(0) as usize;
+        let col = self.col;
+        let line = self.lines.last_mut().unwrap();
+        match action {
+            'm' => self.sgr(params),
+
+            // Erase in line
+            'K' => match count {
+                0 => line.truncate(col),
+                1 => for cell in line.iter_mut().take(col + 1) {
+                    *cell = (' ', Style::default());
+                }
+                2 => line.clear(),
+                _ => {}
+            }
+
+            // Movement within the line
+            'G' => self.col = (count.max(1) - 1).min(MAX_COLUMNS),
+            'C' => self.col = col.saturating_add(count.max(1))
+                .min(MAX_COLUMNS),
+            'D' => self.col = col.saturating_sub(count.max(1)),
+
+            // Anything else moves around the screen, which we can't show
+            _ => {}
+        }
+    }
+}
+
+/// Renders terminal output with ANSI colors into a terminal window, eg.
+/// ```` ```ansi ```` or ```` ```cuddly_terminal ```` blocks of fuzzer output
+#[derive(Default)]
+pub(crate) struct Terminal;
+
+#[async_trait]
+impl Handler for Terminal {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let mut input = input.to_string();
+        for escape in ESCAPES {
+            input = input.replace(escape, "\x1b");
+        }
+
+        let mut screen = Screen {
+            lines: vec![Vec::new()],
+            ..Screen::default()
+        };
+        Parser::new().advance(&mut screen, input.as_bytes());
+
+        let (background, foreground) = theme_colors(website);
+        let output = handler_template(website, ctx, "terminal.html").await?;
+        Ok(output.replace("<<<PUT THE BACKGROUND HERE>>>", &background)
+            .replace("<<<PUT THE FOREGROUND HERE>>>", &foreground)
+            .replace("<<<PUT THE OUTPUT HERE>>>",
+                &screen.render(&foreground, &background)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vte::Parser;
+    use super::{Screen, Style, PALETTE, MAX_COLUMNS, color_256};
+
+    /// Write `input` to a new screen
+    fn screen(input: &str) -> Screen {
+        let mut screen = Screen {
+            lines: vec![Vec::new()],
+            ..Screen::default()
+        };
+        Parser::new().advance(&mut screen, input.as_bytes());
+        screen
+    }
+
+    /// Get the style of every character written by `input`
+    fn styles(input: &str) -> Vec<Style> {
+        screen(input).lines.concat().into_iter().map(|x| x.1).collect()
+    }
+
+    #[test]
+    fn basic_colors() {
+        let red = Style { fg: Some(PALETTE[1]), ..Style::default() };
+        let bright = Style { bg: Some(PALETTE[12]), ..Style::default() };
+        assert_eq!(styles("\x1b[31ma\x1b[0mb\x1b[104mc\x1b[49md"),
+            [red, Style::default(), bright, Style::default()]);
+        assert_eq!(styles("\x1b[31ma\x1b[mb\x1b[31mc\x1b[39md"),
+            [red, Style::default(), red, Style::default()]);
+    }
+
+    #[test]
+    fn attributes() {
+        let [a, b, c] = styles("\x1b[1;2;3;4;7ma\x1b[22;23mb\x1b[24;27mc")[..]
+            else { panic!() };
+        assert!(a.bold && a.dim && a.italic && a.underline && a.inverse);
+        assert!(!b.bold && !b.dim && !b.italic && b.underline && b.inverse);
+        assert_eq!(c, Style::default());
+    }
+
+    #[test]
+    fn extended_colors() {
+        let fg = |input: &str| styles(input)[0].fg;
+        let bg = |input: &str| styles(input)[0].bg;
+        assert_eq!(fg("\x1b[38;5;196mx"), Some((255, 0, 0)));
+        assert_eq!(fg("\x1b[38:5:196mx"), Some((255, 0, 0)));
+        assert_eq!(bg("\x1b[48;2;1;2;3mx"), Some((1, 2, 3)));
+        assert_eq!(fg("\x1b[38:2:4:5:6mx"), Some((4, 5, 6)));
+        assert_eq!(fg("\x1b[38:2::7:8:9mx"), Some((7, 8, 9)));
+
+        // Parameters after an extended color still apply
+        let style = styles("\x1b[38;5;1;1mx")[0];
+        assert_eq!((style.fg, style.bold), (Some(PALETTE[1]), true));
+
+        // Incomplete colors are the default
+        assert_eq!(fg("\x1b[31;38;5mx"), None);
+    }
+
+    #[test]
+    fn palette_256() {
+        assert_eq!(color_256(9), PALETTE[9]);
+        assert_eq!(color_256(16), (0, 0, 0));
+        assert_eq!(color_256(21), (0, 0, 255));
+        assert_eq!(color_256(231), (255, 255, 255));
+        assert_eq!(color_256(232), (8, 8, 8));
+        assert_eq!(color_256(255), (238, 238, 238));
+    }
+
+    #[test]
+    fn line_movement() {
+        let render = |input: &str| screen(input).render("#fff", "#000");
+        assert_eq!(render("50%\r100%\n\n\n"), "100%\n");
+        assert_eq!(render("abcdef\x1b[2K\rxy"), "xy\n");
+        assert_eq!(render("abcdef\r\x1b[3C\x1b[K"), "abc\n");
+        assert_eq!(render("ab\x08c\x1b[1Gd"), "dc\n");
+        assert_eq!(render("a\tb"), "a       b\n");
+        assert_eq!(render("a\x1b[2Jb\x1b[5Ac"), "abc\n");
+    }
+
+    #[test]
+    fn cursor_stays_on_screen() {
+        // Repeated moves can't take the cursor past the edge either
+        let far = format!("a{}b\x1b[999999999Gc\t",
+            "\x1b[65535C".repeat(1000));
+        assert_eq!(screen(&far).lines, [vec![('a', Style::default())]]);
+
+        // The last column can still be written
+        let last = screen(&format!("\x1b[{}Gx", MAX_COLUMNS));
+        assert_eq!(last.lines[0].len(), MAX_COLUMNS);
+    }
+
+    #[test]
+    fn renders_html() {
+        let html = screen("\x1b[1m<b>\x1b[0m & \x1b[7mx")
+            .render("#fff", "#000");
+        assert_eq!(html, "<span style=\"font-weight:bold;\">&lt;b&gt;\
+            </span> &amp; <span style=\"color:#000;background-color:#fff;\">\
+            x</span>\n");
+    }
+}
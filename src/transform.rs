@@ -0,0 +1,473 @@
+//! Transforms applied to the pulldown-cmark events of markdown before it's
+//! turned into HTML
+
+use std::sync::Arc;
+use std::ops::{Range, RangeInclusive};
+use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use async_trait::async_trait;
+use pulldown_cmark::{Event, Tag, CodeBlockKind};
+use crate::{Result, Website, HandlerContext, TemplateInfo};
+use crate::{assets, links, shortcodes};
+use crate::assets::AssetUsage;
+use crate::shortcodes::Segment;
+use crate::website::Source;
+
+/// Markdown events, along with the byte range of the markdown they came from
+pub type Events<'a> = Vec<(Event<'a>, Range<usize>)>;
+
+/// A transform over the events of a piece of markdown. Transforms are
+/// registered by name with [`crate::WebsiteBuilder::register_transform`], and
+/// `config.transforms` picks which ones run and in what order.
+///
+/// The built-in transforms, in their default order, are:
+///
+/// - `templateinfo`: hides `templateinfo` blocks. The metadata is extracted
+///   before any transforms run, so handlers can use it
+/// - `handlers`: replaces `cuddly_*` blocks with the output of their
+///   handlers, ```` ```dot ```` blocks with the `graph` handler's, and
+///   ```` ```ansi ```` blocks with the `terminal` handler's
+/// - `shortcodes`: replaces shortcodes in text with the output of their
+///   handlers
+/// - `emoji`: replaces things like `:heart:` with emoji, outside of code
+/// - `highlight`: highlights fenced code blocks with syntect
+/// - `links`: points links to markdown files at the pages they generate,
+///   decorates external links, and records links and images so they can be
+///   checked and shipped with the website
+/// - `headings`: gives headings ids which are unique within the page
+#[async_trait]
+pub trait Transform: Send + Sync {
+    /// Transform the `events` of the markdown described by `ctx`
+    async fn transform<'a>(&self, events: Events<'a>, website: &Arc<Website>,
+        ctx: &mut TransformContext<'_>) -> Result<Events<'a>>;
+}
+
+/// The markdown being transformed
+pub struct TransformContext<'a> {
+    /// Markdown file being rendered
+    pub source: &'a Path,
+
+    /// URL of the page being generated, eg. `/blog/blog1.html`
+    pub page_url: &'a str,
+
+    /// Metadata of the page being generated, if it has any
+    pub page: Option<&'a TemplateInfo>,
+
+    /// The markdown, which the ranges of the events index into
+    pub markdown: &'a str,
+
+    /// Where the markdown came from
+    pub(crate) inner: &'a Source<'a>,
+
+    /// Ids given to headings so far, and how many times
+    pub(crate) heading_ids: &'a mut HashMap<String, usize>,
+
+    /// Ranges of the events holding the output of handlers, whose links were
+    /// recorded when the handlers ran
+    pub(crate) handler_output: HashSet<Range<usize>>,
+}
+
+impl<'a> TransformContext<'a> {
+    /// Create the context for transforming `markdown` from `source`
+    pub(crate) fn new(markdown: &'a str, source: &'a Source<'a>,
+            heading_ids: &'a mut HashMap<String, usize>) -> Self {
+        TransformContext {
+            source:   source.path,
+            page_url: source.page_url,
+            page:     source.page,
+            inner:    source,
+            markdown,
+            heading_ids,
+            handler_output: HashSet::new(),
+        }
+    }
+
+    /// Get the 1-indexed line in `source` of the event at `range`. Markdown
+    /// returned by a handler is all attributed to the handler's block
+    pub fn line(&self, range: &Range<usize>) -> usize {
+        self.inner.block_line
+            .unwrap_or_else(|| links::line_of(self.markdown, range.start))
+    }
+
+    /// Get the lines in `source` which the event at `range` spans
+    pub fn lines(&self, range: &Range<usize>) -> RangeInclusive<usize> {
+        let end = self.inner.block_line.unwrap_or_else(||
+            links::line_of(self.markdown, range.end.saturating_sub(1)));
+        self.line(range)..=end
+    }
+
+    /// Make `id` unique within the page, by adding a number to ids which
+    /// were already handed out
+    pub fn unique_id(&mut self, id: String) -> String {
+        let count = self.heading_ids.entry(id.clone()).or_insert(0);
+        *count += 1;
+        if *count > 1 {
+            format!("{}-{}", id, *count - 1)
+        } else {
+            id
+        }
+    }
+}
+
+/// Get the language of a fenced code block starting or ending with `event`
+fn fenced_lang<'a, 'b>(event: &'b Event<'a>) -> Option<&'b str> {
+    match event {
+        Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang))) |
+        Event::End(Tag::CodeBlock(CodeBlockKind::Fenced(lang))) =>
+            Some(lang.as_ref()),
+        _ => None,
+    }
+}
+
+/// Languages of fenced code blocks which go to a handler other than
+/// `cuddly_<name>`, and the name of the handler
+const HANDLER_LANGS: &[(&str, &str)] = &[
+    ("dot",  "graph"),
+    ("ansi", "terminal"),
+];
+
+/// Get the name of the handler for fenced code blocks of `lang`, if they have
+/// one
+fn handler_name(lang: &str) -> Option<&str> {
+    lang.strip_prefix("cuddly_").or_else(|| {
+        HANDLER_LANGS.iter().find(|x| x.0 == lang).map(|x| x.1)
+    })
+}
+
+/// Hides `templateinfo` blocks
+pub(crate) struct TemplateInfoTransform;
+
+#[async_trait]
+impl Transform for TemplateInfoTransform {
+    async fn transform<'a>(&self, events: Events<'a>, _website: &Arc<Website>,
+            _ctx: &mut TransformContext<'_>) -> Result<Events<'a>> {
+        let mut in_template_info = false;
+        let mut ret = Vec::new();
+        for (event, range) in events {
+            match (&event, fenced_lang(&event)) {
+                (Event::Start(_), Some("templateinfo")) => {
+                    in_template_info = true;
+                }
+                (Event::End(_), Some("templateinfo")) => {
+                    in_template_info = false;
+                }
+                _ if !in_template_info => ret.push((event, range)),
+                _ => {}
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// Replaces `cuddly_*` blocks, and blocks of the [`HANDLER_LANGS`], with the
+/// output of their handlers
+pub(crate) struct HandlersTransform;
+
+#[async_trait]
+impl Transform for HandlersTransform {
+    async fn transform<'a>(&self, events: Events<'a>, website: &Arc<Website>,
+            ctx: &mut TransformContext<'_>) -> Result<Events<'a>> {
+        // The handler, range, and contents of the block we're in
+        let mut block: Option<(String, Range<usize>, String)> = None;
+
+        let mut ret = Vec::new();
+        for (event, range) in events {
+            let handler = fenced_lang(&event).and_then(handler_name);
+            match (&event, handler, block.as_mut()) {
+                (Event::Start(_), Some(handler), _) => {
+                    block = Some((handler.to_string(), range, String::new()));
+                }
+                (Event::Text(text), _, Some((_, _, input))) => {
+                    input.push_str(text);
+                }
+                (Event::End(_), Some(_), Some(_)) => {
+                    let (handler, range, input) = block.take().unwrap();
+
+                    // Invoke the Rust handler
+                    let handler_ctx = HandlerContext::new(ctx.source,
+                        ctx.page_url, ctx.page, ctx.lines(&range), false,
+                        ctx.inner.include_stack);
+                    let html = website.run_handler(&handler, &input,
+                        handler_ctx, ctx.inner, ctx.heading_ids).await?;
+                    ctx.handler_output.insert(range.clone());
+                    ret.push((Event::Html(html.into()), range));
+                }
+                _ => ret.push((event, range)),
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// Replaces shortcodes in text outside of code blocks with the output of
+/// their handlers
+pub(crate) struct ShortcodesTransform;
+
+#[async_trait]
+impl Transform for ShortcodesTransform {
+    async fn transform<'a>(&self, events: Events<'a>, website: &Arc<Website>,
+            ctx: &mut TransformContext<'_>) -> Result<Events<'a>> {
+        // Text can be split into several events, eg. around entities, so
+        // join it back up to find shortcodes spanning them
+        let mut in_code = false;
+        let mut merged: Events<'a> = Vec::new();
+        for (event, range) in events {
+            match (&event, merged.last_mut()) {
+                (Event::Start(Tag::CodeBlock(_)), _) => in_code = true,
+                (Event::End(Tag::CodeBlock(_)), _) => in_code = false,
+                (Event::Text(text), Some((Event::Text(prev), prev_range)))
+                        if !in_code => {
+                    *prev = format!("{}{}", prev, text).into();
+                    prev_range.end = range.end;
+                    continue;
+                }
+                _ => {}
+            }
+            merged.push((event, range));
+        }
+
+        let mut in_code = false;
+        let mut ret = Vec::new();
+        for (event, range) in merged {
+            match event {
+                Event::Start(Tag::CodeBlock(_)) => in_code = true,
+                Event::End(Tag::CodeBlock(_)) => in_code = false,
+                Event::Text(ref text) if !in_code &&
+                        (text.contains("{{") || text.contains("{%")) => {
+                    // The output goes in the middle of the text
+                    for segment in shortcodes::parse(text) {
+                        let line = ctx.line(&range);
+                        let mut handler_ctx = HandlerContext::new(ctx.source,
+                            ctx.page_url, ctx.page, line..=line, true,
+                            ctx.inner.include_stack);
+                        match segment {
+                            Segment::Text(text) => {
+                                ret.push((Event::Text(text.to_string().into()),
+                                    range.clone()));
+                            }
+
+                            // Leave what only looks like a shortcode alone
+                            Segment::Shortcode { name, text, .. }
+                                    if !website.has_handler(name) => {
+                                handler_ctx.warn(format!("`{}` isn't a \
+                                    handler, leaving `{}` as text", name,
+                                    text));
+                                website.apply_context(handler_ctx).await?;
+                                ret.push((Event::Text(text.to_string().into()),
+                                    range.clone()));
+                            }
+                            Segment::Shortcode { name, args, .. } => {
+                                let html = website.run_handler(name, args,
+                                    handler_ctx, ctx.inner, ctx.heading_ids)
+                                    .await?;
+                                ctx.handler_output.insert(range.clone());
+                                ret.push((Event::Html(html.into()),
+                                    range.clone()));
+                            }
+                        }
+                    }
+                    continue;
+                }
+                _ => {}
+            }
+            ret.push((event, range));
+        }
+        Ok(ret)
+    }
+}
+
+/// Converts things like `:heart:` in text outside of code blocks into their
+/// unicode equivalents
+pub(crate) struct EmojiTransform;
+
+#[async_trait]
+impl Transform for EmojiTransform {
+    async fn transform<'a>(&self, mut events: Events<'a>,
+            website: &Arc<Website>, _ctx: &mut TransformContext<'_>)
+            -> Result<Events<'a>> {
+        let mut in_code = false;
+        for (event, _) in &mut events {
+            match event {
+                Event::Start(Tag::CodeBlock(_)) => in_code = true,
+                Event::End(Tag::CodeBlock(_)) => in_code = false,
+                Event::Text(text) if !in_code => {
+                    // If emoji replacement actually did anything, then update
+                    // the old text with the new, replaced text
+                    if let std::borrow::Cow::Owned(new_text) =
+                            website.emoji_replacer.replace_all(text) {
+                        *text = new_text.into();
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(events)
+    }
+}
+
+/// Highlights fenced code blocks in languages we have a syntax for
+pub(crate) struct HighlightTransform;
+
+#[async_trait]
+impl Transform for HighlightTransform {
+    async fn transform<'a>(&self, mut events: Events<'a>,
+            website: &Arc<Website>, _ctx: &mut TransformContext<'_>)
+            -> Result<Events<'a>> {
+        let mut syntax = None;
+        for (event, _) in &mut events {
+            match (&*event, fenced_lang(event)) {
+                (Event::Start(_), Some(lang)) => {
+                    syntax = website.syntax_set.find_syntax_by_token(lang);
+                }
+                (Event::End(_), Some(_)) => syntax = None,
+                (Event::Text(text), _) if syntax.is_some() => {
+                    // Perform syntax highlighting by converting the string to
+                    // HTML with coloring
+                    let hled = syntect::html::highlighted_html_for_string(
+                        text, &website.syntax_set, syntax.unwrap(),
+                        &website.theme);
+                    *event = Event::Html(hled.into());
+                }
+                _ => {}
+            }
+        }
+        Ok(events)
+    }
+}
+
+/// Points links to markdown files at the pages they generate, decorates
+/// external links, and records links and images so they can be checked and
+/// shipped with the website
+pub(crate) struct LinksTransform;
+
+/// Get the directory part of `url`, including the trailing `/`
+fn url_dir(url: &str) -> &str {
+    &url[..url.rfind('/').map(|x| x + 1).unwrap_or(0)]
+}
+
+#[async_trait]
+impl Transform for LinksTransform {
+    async fn transform<'a>(&self, mut events: Events<'a>,
+            website: &Arc<Website>, ctx: &mut TransformContext<'_>)
+            -> Result<Events<'a>> {
+        let source = ctx.inner;
+
+        // Pages which a permalink moved away from their markdown need
+        // relative links based on where the markdown is, as that's where the
+        // files they point to are
+        let markdown_url = website.output_file(source.path, None)?.1;
+        let moved = url_dir(&markdown_url) != url_dir(source.base_url);
+        let rebase = |dest: &str| {
+            if !moved || !assets::is_local(dest) || dest.starts_with('/') {
+                return None;
+            }
+            let (path, rest) = dest.split_at(dest.find(['?', '#'])
+                .unwrap_or(dest.len()));
+            links::resolve_url(&markdown_url, path).map(|x| x + rest)
+        };
+
+        // For each link we're in, whether we turned it into raw HTML because
+        // it's an external link
+        let mut link_stack = Vec::new();
+
+        for (event, range) in &mut events {
+            let line = ctx.line(range);
+            match event {
+                // Images which refer to local files need to be shipped with
+                // the website
+                Event::Start(Tag::Image(_, dest, _)) => {
+                    if assets::is_local(dest) {
+                        website.track_asset(assets::resolve_local(
+                            &website.config.content_path, source.path, dest),
+                            AssetUsage::Copied);
+                    }
+                    if let Some(url) = rebase(dest) {
+                        *dest = url.into();
+                    }
+                    website.record_link(source.path, line, source.base_url,
+                        dest);
+                }
+
+                // Point links to markdown files at the pages they generate,
+                // and save links so they can be checked
+                Event::Start(Tag::Link(_, dest, title)) => {
+                    if let Some(url) =
+                            website.md_link_url(source.path, dest).await? {
+                        *dest = url.into();
+                    } else if let Some(url) = rebase(dest) {
+                        *dest = url.into();
+                    }
+                    website.record_link(source.path, line, source.base_url,
+                        dest);
+
+                    // Optionally decorate links which leave the website
+                    let external = links::is_external(dest) &&
+                        website.config.external_links.enabled();
+                    if external {
+                        let html = website.config.external_links
+                            .open_tag(dest, title);
+                        *event = Event::Html(html.into());
+                    }
+                    link_stack.push(external);
+                }
+                Event::End(Tag::Link(..)) => {
+                    let external = link_stack.pop();
+                    if external == Some(true) {
+                        let html = format!("{}</a>",
+                            website.config.external_links.marker.as_deref()
+                                .unwrap_or(""));
+                        *event = Event::Html(html.into());
+                    }
+                }
+                // Links in the HTML of handlers were recorded when they ran
+                Event::Html(html) if !ctx.handler_output.contains(range) => {
+                    website.record_links(source.path, line, source.base_url,
+                        html);
+                }
+                _ => {}
+            }
+        }
+        Ok(events)
+    }
+}
+
+/// Gives headings ids which are unique within the page, made from their text
+pub(crate) struct HeadingsTransform;
+
+#[async_trait]
+impl Transform for HeadingsTransform {
+    async fn transform<'a>(&self, mut events: Events<'a>,
+            _website: &Arc<Website>, ctx: &mut TransformContext<'_>)
+            -> Result<Events<'a>> {
+        // Index of the start of the active heading
+        let mut cur_heading = None;
+
+        for idx in 0..events.len() {
+            match events[idx].0 {
+                Event::Start(Tag::Heading(_)) => cur_heading = Some(idx),
+                Event::End(Tag::Heading(level)) => {
+                    let start = match cur_heading.take() {
+                        Some(start) => start,
+                        None        => continue,
+                    };
+
+                    // Create an id from the text of the heading
+                    let mut text = String::new();
+                    for (event, _) in &events[start..idx] {
+                        if let Event::Text(x) | Event::Code(x) = event {
+                            text += x;
+                        }
+                    }
+                    let id = ctx.unique_id(links::slugify(&text));
+
+                    events[start].0 = Event::Html(
+                        format!("<h{} id=\"{}\">", level, id).into());
+                    events[idx].0 =
+                        Event::Html(format!("</h{}>\n", level).into());
+                }
+                _ => {}
+            }
+        }
+        Ok(events)
+    }
+}
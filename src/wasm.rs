@@ -0,0 +1,395 @@
+//! Handlers implemented by WebAssembly plugins
+//!
+//! Every `<name>.wasm` in the plugins directory handles `cuddly_<name>` code
+//! blocks. Plugins run in an interpreter with a fuel and memory limit, so a
+//! broken plugin stops the build with [`Error::PluginFailed`] rather than
+//! hanging it or exhausting the host's memory.
+//!
+//! A plugin exports its `memory` along with:
+//!
+//! ```text
+//! cuddly_alloc(len: i32) -> i32
+//! cuddly_handle(ptr: i32, len: i32) -> i64
+//! ```
+//!
+//! The request JSON (the same one [`crate::ProcessHandler`]s get) is written
+//! into a buffer from `cuddly_alloc`, and `cuddly_handle` returns the address
+//! of the response JSON in the upper 32 bits and its length in the lower 32
+//! bits. Plugins have no filesystem access, other than reading files inside
+//! `config.content_path` through these imports from the `cuddly` module:
+//!
+//! ```text
+//! file_size(path_ptr: i32, path_len: i32) -> i64
+//! read_file(path_ptr: i32, path_len: i32, buf_ptr: i32, buf_len: i32) -> i64
+//! ```
+//!
+//! Both return -1 on failure, and `read_file` returns the number of bytes
+//! read, which is at most `buf_len`. Files read this way become dependencies
+//! of the page.
+
+use std::sync::Arc;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use async_trait::async_trait;
+use serde_derive::Deserialize;
+use wasmi::{Caller, Engine, Extern, Linker, Module, Store, StoreLimits};
+use wasmi::StoreLimitsBuilder;
+use crate::{Error, Result, Website, Handler, HandlerContext};
+use crate::process::{Request, Response};
+
+/// Limits for WebAssembly plugins, from the `[plugins]` section of the config
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PluginConfig {
+    /// Directory containing the plugins, relative to `content_path`
+    pub path: PathBuf,
+
+    /// Number of instructions (roughly) a plugin may execute per block
+    pub fuel: u64,
+
+    /// Maximum size of a plugin's memory, in bytes
+    pub max_memory: usize,
+}
+
+impl Default for PluginConfig {
+    fn default() -> Self {
+        PluginConfig {
+            path:       "plugins".into(),
+            fuel:       100_000_000,
+            max_memory: 64 * 1024 * 1024,
+        }
+    }
+}
+
+/// State available to the host functions while a plugin runs
+struct HostState {
+    /// `content_path` as configured, which dependencies are recorded under
+    content_path: PathBuf,
+
+    /// Canonicalized `content_path`, which all file access must stay inside
+    root: PathBuf,
+
+    /// Limits on the plugin's memory
+    limits: StoreLimits,
+
+    /// Files the plugin read
+    dependencies: Vec<PathBuf>,
+}
+
+impl HostState {
+    /// Resolve the guest string at `ptr` of `len` bytes to a file inside
+    /// `content_path`, returning its canonical path
+    fn resolve(caller: &Caller<HostState>, ptr: i32, len: i32)
+            -> Option<PathBuf> {
+        let memory = caller.get_export("memory")
+            .and_then(Extern::into_memory)?;
+        let path = memory.data(caller)
+            .get(ptr as u32 as usize..)?.get(..len as u32 as usize)?;
+        let path = std::str::from_utf8(path).ok()?;
+
+        let path = std::fs::canonicalize(
+            caller.data().root.join(path.trim_start_matches('/')))
+            .ok()?;
+        path.starts_with(&caller.data().root).then_some(path)
+    }
+
+    /// Get the canonical `path` inside `content_path` as it sits under the
+    /// configured `content_path`, which is how assets are tracked
+    fn dependency(&self, path: &Path) -> PathBuf {
+        self.content_path.join(path.strip_prefix(&self.root).unwrap())
+    }
+}
+
+/// Create the engine plugins are compiled with
+pub(crate) fn engine() -> Engine {
+    let mut config = wasmi::Config::default();
+    config.consume_fuel(true);
+    Engine::new(&config)
+}
+
+/// A handler which runs blocks through a WebAssembly plugin
+pub struct WasmHandler {
+    /// Name of the handler, used for error reporting
+    name: String,
+
+    /// File the plugin was loaded from, which its output depends on
+    path: PathBuf,
+
+    /// Engine the module was compiled with
+    engine: Engine,
+
+    /// The compiled plugin, instantiated fresh for every block
+    module: Arc<Module>,
+
+    /// Limits for running the plugin
+    config: PluginConfig,
+}
+
+impl WasmHandler {
+    /// Compile the plugin `wasm`, loaded from `path`, into a handler named
+    /// `name`
+    pub(crate) fn new(name: impl Into<String>, path: impl Into<PathBuf>,
+            engine: &Engine, wasm: &[u8], config: PluginConfig)
+            -> std::result::Result<Self, wasmi::Error> {
+        Ok(WasmHandler {
+            name:   name.into(),
+            path:   path.into(),
+            engine: engine.clone(),
+            module: Arc::new(Module::new(engine, wasm)?),
+            config,
+        })
+    }
+
+    /// Instantiate the plugin and run `request` through it, returning the
+    /// response and the files the plugin read. `root` is `content_path`
+    /// canonicalized
+    fn run(engine: &Engine, module: &Module, config: &PluginConfig,
+            content_path: PathBuf, root: PathBuf, request: &str)
+            -> std::result::Result<(String, Vec<PathBuf>), String> {
+        let state = HostState {
+            content_path,
+            root,
+            limits: StoreLimitsBuilder::new()
+                .memory_size(config.max_memory).build(),
+            dependencies: Vec::new(),
+        };
+        let mut store = Store::new(engine, state);
+        store.limiter(|state| &mut state.limits);
+        store.add_fuel(config.fuel).map_err(|x| x.to_string())?;
+
+        // Set up the host functions
+        let mut linker = Linker::new(engine);
+        linker.func_wrap("cuddly", "file_size",
+            |caller: Caller<HostState>, ptr: i32, len: i32| -> i64 {
+                HostState::resolve(&caller, ptr, len)
+                    .and_then(|path| std::fs::metadata(path).ok())
+                    .map(|x| x.len() as i64).unwrap_or(-1)
+            }).map_err(|x| x.to_string())?;
+        linker.func_wrap("cuddly", "read_file",
+            |mut caller: Caller<HostState>, ptr: i32, len: i32,
+                    buf_ptr: i32, buf_len: i32| -> i64 {
+                let path = match HostState::resolve(&caller, ptr, len) {
+                    Some(path) => path,
+                    None       => return -1,
+                };
+
+                // Only read what fits, however large the file is
+                let mut contents = Vec::new();
+                let read = std::fs::File::open(&path).and_then(|x|
+                    x.take(buf_len as u32 as u64).read_to_end(&mut contents));
+                if read.is_err() {
+                    return -1;
+                }
+
+                let memory = caller.get_export("memory")
+                    .and_then(Extern::into_memory);
+                match memory.map(|x|
+                        x.write(&mut caller, buf_ptr as u32 as usize,
+                            &contents)) {
+                    Some(Ok(())) => {
+                        let path = caller.data().dependency(&path);
+                        caller.data_mut().dependencies.push(path);
+                        contents.len() as i64
+                    }
+                    _ => -1,
+                }
+            }).map_err(|x| x.to_string())?;
+
+        let instance = linker.instantiate(&mut store, module)
+            .and_then(|x| x.start(&mut store))
+            .map_err(|x| x.to_string())?;
+        let memory = instance.get_memory(&store, "memory")
+            .ok_or("plugin does not export `memory`")?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "cuddly_alloc")
+            .map_err(|x| x.to_string())?;
+        let handle = instance
+            .get_typed_func::<(i32, i32), i64>(&store, "cuddly_handle")
+            .map_err(|x| x.to_string())?;
+
+        // Hand the request to the plugin
+        let len = i32::try_from(request.len())
+            .map_err(|_| "request too large")?;
+        let ptr = alloc.call(&mut store, len).map_err(|x| x.to_string())?;
+        memory.write(&mut store, ptr as u32 as usize, request.as_bytes())
+            .map_err(|x| x.to_string())?;
+        let ret = handle.call(&mut store, (ptr, len))
+            .map_err(|x| x.to_string())? as u64;
+
+        // Get the response out
+        let (ptr, len) = ((ret >> 32) as usize, ret as u32 as usize);
+        let response = memory.data(&store).get(ptr..)
+            .and_then(|x| x.get(..len))
+            .ok_or("response is out of bounds")?;
+        let response = String::from_utf8(response.to_vec())
+            .map_err(|_| "response is not UTF-8")?;
+
+        Ok((response, store.into_data().dependencies))
+    }
+}
+
+#[async_trait]
+impl Handler for WasmHandler {
+    async fn handle(&self, input: &str, website: &Arc<Website>,
+            ctx: &mut HandlerContext<'_>) -> Result<String> {
+        let request = Request::to_json(&self.name, input, ctx)?;
+        let content_path = website.config.content_path.clone();
+        let root = tokio::fs::canonicalize(&content_path).await
+            .map_err(|x| Error::PluginIo(self.name.clone(), x))?;
+
+        // Plugins are interpreted, so run them off the async threads
+        let engine = self.engine.clone();
+        let module = self.module.clone();
+        let config = self.config.clone();
+        let (response, dependencies) = tokio::task::spawn_blocking(move ||
+                Self::run(&engine, &module, &config, content_path, root,
+                    &request))
+            .await
+            .map_err(|x| Error::PluginFailed(self.name.clone(), x.to_string()))?
+            .map_err(|x| Error::PluginFailed(self.name.clone(), x))?;
+
+        // The output changes if the plugin does
+        ctx.add_dependency(&self.path);
+        for dependency in dependencies {
+            ctx.add_dependency(dependency);
+        }
+        Response::apply(&self.name, &response, website, ctx)
+    }
+}
+
+/// Load all plugins from `path` as `(handler name, plugin file, handler)`
+pub(crate) fn load_plugins(path: &Path, config: &PluginConfig)
+        -> Result<Vec<(String, PathBuf, WasmHandler)>> {
+    let mut plugins = Vec::new();
+    if !path.is_dir() {
+        return Ok(plugins);
+    }
+
+    let engine = engine();
+    for dirent in std::fs::read_dir(path)
+            .map_err(|x| Error::ReadDirectory(path.to_path_buf(), x))? {
+        let plugin = dirent
+            .map_err(|x| Error::ReadDirectory(path.to_path_buf(), x))?.path();
+        if plugin.extension().and_then(|x| x.to_str()) != Some("wasm") {
+            continue;
+        }
+
+        let name = plugin.file_stem().unwrap().to_string_lossy().into_owned();
+        let wasm = std::fs::read(&plugin)
+            .map_err(|x| Error::ReadPlugin(plugin.clone(), x))?;
+        let handler = WasmHandler::new(name.clone(), &plugin, &engine, &wasm,
+            config.clone())
+            .map_err(|x| Error::LoadPlugin(plugin.clone(), x))?;
+        plugins.push((name, plugin, handler));
+    }
+
+    Ok(plugins)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use wasmi::Module;
+    use super::{engine, PluginConfig, WasmHandler};
+
+    /// Run the plugin written as WAT in `wat` with `config`, serving files
+    /// from `content_path`
+    fn run(wat: &str, config: &PluginConfig, content_path: &Path)
+            -> Result<(String, Vec<PathBuf>), String> {
+        let engine = engine();
+        let module = Module::new(&engine, &wat::parse_str(wat).unwrap()[..])
+            .unwrap();
+        WasmHandler::run(&engine, &module, config, content_path.into(),
+            std::fs::canonicalize(content_path).unwrap(), "{}")
+    }
+
+    /// A plugin which responds with the first `buf_len` bytes of the file
+    /// at `path`, or `denied` if it can't read it
+    fn reader(path: &str, buf_len: usize) -> String {
+        format!(r#"(module
+            (import "cuddly" "read_file"
+                (func $read (param i32 i32 i32 i32) (result i64)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "denied")
+            (data (i32.const 16) "{}")
+            (func (export "cuddly_alloc") (param i32) (result i32)
+                i32.const 4096)
+            (func (export "cuddly_handle") (param i32 i32) (result i64)
+                (local $len i64)
+                (local.set $len (call $read (i32.const 16) (i32.const {})
+                    (i32.const 1024) (i32.const {})))
+                (if (result i64) (i64.lt_s (local.get $len) (i64.const 0))
+                    (then (i64.const 6))
+                    (else (i64.or (i64.const 0x40000000000)
+                        (local.get $len))))))"#, path, path.len(), buf_len)
+    }
+
+    /// Create an empty directory for a test called `name`
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("wasm-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn fuel_runs_out() {
+        let config = PluginConfig { fuel: 10_000, ..PluginConfig::default() };
+        assert!(run(r#"(module
+            (memory (export "memory") 1)
+            (func (export "cuddly_alloc") (param i32) (result i32)
+                i32.const 0)
+            (func (export "cuddly_handle") (param i32 i32) (result i64)
+                (loop $forever (br $forever))
+                i64.const 0))"#, &config, Path::new(".")).unwrap_err()
+            .contains("fuel"));
+    }
+
+    #[test]
+    fn memory_is_limited() {
+        // Grow by a megabyte, giving up if that isn't allowed
+        let wat = r#"(module
+            (memory (export "memory") 1)
+            (func (export "cuddly_alloc") (param i32) (result i32)
+                i32.const 0)
+            (func (export "cuddly_handle") (param i32 i32) (result i64)
+                (if (i32.eq (memory.grow (i32.const 16)) (i32.const -1))
+                    (then unreachable))
+                i64.const 0))"#;
+        let small = PluginConfig {
+            max_memory: 4 * 65536,
+            ..PluginConfig::default()
+        };
+        assert_eq!(run(wat, &PluginConfig::default(), Path::new("."))
+            .unwrap().0, "");
+        assert!(run(wat, &small, Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn reads_stay_in_content_path() {
+        let dir = temp_dir("sandbox");
+        std::fs::create_dir_all(dir.join("content/sub")).unwrap();
+        std::fs::write(dir.join("content/sub/a.txt"), "0123456789")
+            .unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+        let content_path = dir.join("content");
+        let config = PluginConfig::default();
+
+        // Dependencies are recorded under the configured `content_path`
+        assert_eq!(run(&reader("/sub/../sub/a.txt", 100), &config,
+                &content_path).unwrap(),
+            ("0123456789".into(), vec![content_path.join("sub/a.txt")]));
+
+        // Only as much as fits in the buffer is read
+        assert_eq!(run(&reader("sub/a.txt", 4), &config, &content_path)
+            .unwrap().0, "0123");
+
+        for path in ["../secret.txt", "/../secret.txt", "sub/../../secret.txt",
+                "missing.txt"] {
+            assert_eq!(run(&reader(path, 100), &config, &content_path)
+                .unwrap(), ("denied".into(), vec![]), "{}", path);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
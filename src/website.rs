@@ -0,0 +1,1127 @@
+//! Website generation
+
+use std::sync::{Arc, Mutex, OnceLock};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::future::Future;
+use std::collections::{HashMap, BTreeMap, BTreeSet};
+use chrono::DateTime;
+use syntect::parsing::SyntaxSet;
+use syntect::highlighting::{Theme, ThemeSet};
+use gh_emoji::Replacer;
+use serde_derive::{Serialize, Deserialize};
+use pulldown_cmark::{Parser, html, Event, Tag, CodeBlockKind};
+use pulldown_cmark::escape::escape_html;
+use crate::{Error, Result, Config, Options, Handler, HandlerContext};
+use crate::{ProcessHandler, Transform, TransformContext};
+use crate::{Rewriter, RewriteContext, CollectionQuery};
+use crate::{svg, assets, links, wasm, script, rewrite, data, collection};
+use crate::permalink;
+use crate::cache::HandlerCache;
+use crate::assets::AssetUsage;
+use crate::handlers::{Header, Include, Index, Icon, PostLink, Kbd, Date};
+use crate::binary::{Hexdump, Disasm};
+use crate::terminal::Terminal;
+use crate::chart::Chart;
+use crate::graph::Graph;
+use crate::table::Table;
+use crate::data::{Data, DataFile};
+use crate::transform::{TemplateInfoTransform, HandlersTransform};
+use crate::transform::{ShortcodesTransform, EmojiTransform};
+use crate::transform::{HighlightTransform, LinksTransform, HeadingsTransform};
+use crate::rewrite::{ActiveRewriter, LazyImages, WrapTables, HeadingAnchors};
+
+/// Template info included in the markdown file indicating information to be
+/// used to render the HTML page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateInfo {
+    /// Path to the CSS to use for the stylesheet for this page. Pages in a
+    /// collection default to the collection's
+    /// This is relative to `config.content_path`
+    pub style: PathBuf,
+
+    /// Path to the template to use for the HTML for this page. Pages in a
+    /// collection default to the collection's
+    /// This is relative to `config.content_path`
+    pub template: PathBuf,
+
+    /// Path to the ICO file to use as a favicon. If not specified, it will
+    /// default to `favicon.ico`
+    /// This is relative to `config.content_path`
+    #[serde(default = "default_favicon")]
+    pub favicon: PathBuf,
+
+    /// Time stamp for the page
+    pub time: DateTime<chrono::Local>,
+
+    /// Title of the webpage
+    pub title: String,
+
+    /// Description of the page, also used for the OpenGraph
+    pub description: String,
+
+    /// Name of the page in its permalink, instead of the file name of the
+    /// markdown
+    pub slug: Option<String>,
+
+    /// Where the page is generated, instead of its collection's permalink,
+    /// eg. `/talks/{year}/{slug}/`. See
+    /// [`crate::CollectionConfig::permalink`]
+    pub permalink: Option<String>,
+}
+
+/// Default favicon path if one is not specified by markdown
+fn default_favicon() -> PathBuf {
+    PathBuf::from("favicon.ico")
+}
+
+/// A website generation session, can be shared between threads immutably
+pub struct Website {
+    /// Theme to use for coloring code snippits
+    pub theme: Theme,
+
+    /// Syntax set of supported syntaxes
+    pub syntax_set: SyntaxSet,
+
+    /// Emoji replacer (eg. `:smile:` to unicode smiley face)
+    pub emoji_replacer: Replacer,
+
+    /// Parsed configuration file for this website
+    pub config: Config,
+
+    /// HTML for the processed header, rendered when the build starts
+    header: OnceLock<String>,
+
+    /// Mapping of handler names to their Rust `Handler`s
+    handlers: HashMap<String, Box<dyn Handler>>,
+
+    /// Transforms applied to markdown, in order, along with their names
+    transforms: Vec<(String, Box<dyn Transform>)>,
+
+    /// Rewriters run over the HTML of every page, in order
+    rewriters: Vec<ActiveRewriter>,
+
+    /// Files loaded from `config.data_path`, keyed by file stem
+    data: BTreeMap<String, DataFile>,
+
+    /// Sanitized SVG symbols which have been loaded, keyed by symbol id. Each
+    /// page gets a sprite with the symbols it references
+    svg_symbols: Mutex<BTreeMap<String, svg::Symbol>>,
+
+    /// Every file under `content_path` which has been referenced, and how it
+    /// was used
+    assets: Mutex<BTreeMap<PathBuf, AssetUsage>>,
+
+    /// Internal links found in the website, checked once generation is done
+    links: Mutex<BTreeSet<links::Link>>,
+
+    /// Files which have been written, relative to `output_path`, and the ids
+    /// of the elements in them
+    outputs: Mutex<BTreeMap<String, BTreeSet<String>>>,
+
+    /// Markdown files which still need to be generated into pages
+    page_queue: Mutex<PageQueue>,
+
+    /// Warnings emitted by handlers
+    warnings: Mutex<Vec<Warning>>,
+
+    /// Files which handlers declared the output of markdown files depend on
+    dependencies: Mutex<BTreeMap<PathBuf, BTreeSet<PathBuf>>>,
+
+    /// Extra files written by handlers
+    extra_outputs: Mutex<Vec<PathBuf>>,
+
+    /// Markdown each page was generated from, or the collection directory
+    /// of each feed, keyed by the file relative to `output_path`
+    page_sources: Mutex<HashMap<PathBuf, PathBuf>>,
+
+    /// Cache of handler output, if enabled
+    cache: Option<HandlerCache>,
+
+    /// Options for this generation session
+    options: Options,
+}
+
+/// Markdown files which have been requested to be generated as pages
+#[derive(Default)]
+struct PageQueue {
+    /// Every markdown file which has ever been queued
+    queued: BTreeSet<PathBuf>,
+
+    /// Markdown files which have not been generated yet
+    pending: Vec<PathBuf>,
+}
+
+/// What was generated by [`Website::build`]
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    /// Every page which was written, in the order they were generated
+    pub pages: Vec<PathBuf>,
+
+    /// Every asset which was copied into the output directory
+    pub assets: Vec<PathBuf>,
+
+    /// Internal links which don't point to anything which was generated
+    pub broken_links: Vec<BrokenLink>,
+
+    /// Files under `content_path` which nothing referenced. This is only
+    /// populated with [`Options::report_unused`]
+    pub unused: Vec<PathBuf>,
+
+    /// Warnings emitted by handlers
+    pub warnings: Vec<Warning>,
+
+    /// For each markdown file, the files which handlers in it declared they
+    /// depend on. If any of these change, the markdown needs regenerating
+    pub dependencies: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+
+    /// Number of handler blocks whose output came from the cache, see
+    /// [`Config::cache_path`]
+    pub cached: usize,
+}
+
+/// A warning emitted by a handler
+#[derive(Debug)]
+pub struct Warning {
+    /// Markdown file containing the handler's block
+    pub source: PathBuf,
+
+    /// Line the handler's block starts on
+    pub line: usize,
+
+    /// The warning itself
+    pub message: String,
+}
+
+/// An internal link which doesn't point to anything which was generated
+#[derive(Debug)]
+pub struct BrokenLink {
+    /// The link itself
+    pub link: links::Link,
+
+    /// Why the link is broken
+    pub problem: String,
+}
+
+/// Maximum depth of handlers returning markdown which invokes handlers
+const MAX_HANDLER_DEPTH: usize = 16;
+
+/// Where markdown being rendered came from
+#[derive(Clone, Copy)]
+pub(crate) struct Source<'a> {
+    /// Markdown file being rendered
+    pub(crate) path: &'a Path,
+
+    /// URL the markdown file will be at, used to resolve relative links
+    pub(crate) base_url: &'a str,
+
+    /// URL of the page being generated
+    pub(crate) page_url: &'a str,
+
+    /// Metadata of the page being generated
+    pub(crate) page: Option<&'a TemplateInfo>,
+
+    /// Canonical paths of the markdown files being rendered, outermost first
+    pub(crate) include_stack: &'a [PathBuf],
+
+    /// If the markdown was returned by a handler, the line of the handler's
+    /// block in `path`, which everything in the markdown is attributed to
+    pub(crate) block_line: Option<usize>,
+
+    /// How many handlers returning markdown deep this is
+    depth: usize,
+}
+
+/// Sets up a [`Website`], allowing the defaults derived from the [`Config`]
+/// to be overridden and custom [`Handler`]s, [`Transform`]s and
+/// [`Rewriter`]s to be registered
+pub struct WebsiteBuilder {
+    /// Configuration for the website
+    config: Config,
+
+    /// Theme to use instead of `config.syntax_theme`
+    theme: Option<Theme>,
+
+    /// Syntax set to use instead of the defaults and `syntaxes` folder
+    syntax_set: Option<SyntaxSet>,
+
+    /// Mapping of handler names to their Rust `Handler`s
+    handlers: HashMap<String, Box<dyn Handler>>,
+
+    /// Transforms which can be applied to markdown, in their default order
+    transforms: Vec<(String, Box<dyn Transform>)>,
+
+    /// Rewriters which can be run over the HTML of pages, keyed by name
+    rewriters: HashMap<String, Box<dyn Rewriter>>,
+
+    /// Options for the generation session
+    options: Options,
+}
+
+impl WebsiteBuilder {
+    /// Start building a website from a `config`, with the built-in handlers,
+    /// the process handlers from the config, and the built-in transforms and
+    /// rewriters registered
+    pub fn new(config: Config) -> Self {
+        let process_handlers = config.handlers.clone();
+
+        let mut builder = WebsiteBuilder {
+            config,
+            theme:      None,
+            syntax_set: None,
+            handlers:   HashMap::new(),
+            transforms: Vec::new(),
+            rewriters:  HashMap::new(),
+            options:    Options::default(),
+        }
+        .register_handler("header", Header)
+        .register_handler("include", Include)
+        .register_handler("index", Index)
+        .register_handler("icon", Icon)
+        .register_handler("post_link", PostLink)
+        .register_handler("kbd", Kbd)
+        .register_handler("date", Date)
+        .register_handler("hexdump", Hexdump)
+        .register_handler("disasm", Disasm)
+        .register_handler("terminal", Terminal)
+        .register_handler("chart", Chart)
+        .register_handler("graph", Graph)
+        .register_handler("table", Table)
+        .register_handler("data", Data)
+        .register_transform("templateinfo", TemplateInfoTransform)
+        .register_transform("handlers", HandlersTransform)
+        .register_transform("shortcodes", ShortcodesTransform)
+        .register_transform("emoji", EmojiTransform)
+        .register_transform("highlight", HighlightTransform)
+        .register_transform("links", LinksTransform)
+        .register_transform("headings", HeadingsTransform)
+        .register_rewriter("lazy_images", LazyImages)
+        .register_rewriter("wrap_tables", WrapTables)
+        .register_rewriter("heading_anchors", HeadingAnchors);
+
+        for (name, handler) in process_handlers {
+            builder = builder.register_handler(name.clone(),
+                ProcessHandler::new(name, handler));
+        }
+        builder
+    }
+
+    /// Start building a website based on a configuration TOML file
+    pub async fn from_path(config_toml: impl AsRef<Path>) -> Result<Self> {
+        // Read the config toml
+        let config = tokio::fs::read_to_string(&config_toml).await
+            .map_err(|x|
+                Error::ConfigRead(config_toml.as_ref().to_path_buf(), x))?;
+
+        // Parse the config
+        let config = toml::from_str::<Config>(&config)
+            .map_err(|x|
+                Error::ConfigParse(config_toml.as_ref().to_path_buf(), x))?;
+
+        Ok(Self::new(config))
+    }
+
+    /// Make `handler` handle `cuddly_<name>` code blocks. This replaces any
+    /// existing handler with the same name, including built-in ones
+    pub fn register_handler(mut self, name: impl Into<String>,
+            handler: impl Handler + 'static) -> Self {
+        self.handlers.insert(name.into(), Box::new(handler));
+        self
+    }
+
+    /// Make `transform` available to apply to markdown as `name`. This
+    /// replaces any existing transform with the same name, including built-in
+    /// ones, and new transforms run after the existing ones unless
+    /// `config.transforms` says otherwise
+    pub fn register_transform(mut self, name: impl Into<String>,
+            transform: impl Transform + 'static) -> Self {
+        let name = name.into();
+        match self.transforms.iter_mut().find(|(x, _)| *x == name) {
+            Some((_, existing)) => *existing = Box::new(transform),
+            None => self.transforms.push((name, Box::new(transform))),
+        }
+        self
+    }
+
+    /// Make `rewriter` available to `config.rewriters` as `name`. This
+    /// replaces any existing rewriter with the same name, including built-in
+    /// ones
+    pub fn register_rewriter(mut self, name: impl Into<String>,
+            rewriter: impl Rewriter + 'static) -> Self {
+        self.rewriters.insert(name.into(), Box::new(rewriter));
+        self
+    }
+
+    /// Use `theme` for syntax highlighting rather than `config.syntax_theme`
+    pub fn theme(mut self, theme: Theme) -> Self {
+        self.theme = Some(theme);
+        self
+    }
+
+    /// Use `syntax_set` for syntax highlighting rather than syntect's default
+    /// syntaxes and the `syntaxes` folder
+    pub fn syntax_set(mut self, syntax_set: SyntaxSet) -> Self {
+        self.syntax_set = Some(syntax_set);
+        self
+    }
+
+    /// Set the options for the generation session
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Create the website, loading the handler scripts, WebAssembly plugins,
+    /// and data files. Scripts and plugins don't replace handlers which are
+    /// already registered, and scripts take priority over plugins
+    pub fn build(mut self) -> Result<Website> {
+        let mut plugin_files = Vec::new();
+        let scripts = script::load_scripts(
+            &self.config.content_path.join("handlers"))?;
+        for (name, path, handler) in scripts {
+            self.handlers.entry(name).or_insert_with(|| Box::new(handler));
+            plugin_files.push(path);
+        }
+
+        let plugins = wasm::load_plugins(
+            &self.config.content_path.join(&self.config.plugins.path),
+            &self.config.plugins)?;
+        for (name, path, handler) in plugins {
+            self.handlers.entry(name).or_insert_with(|| Box::new(handler));
+            plugin_files.push(path);
+        }
+
+        // Pick the transforms the config asks for, in its order
+        let transforms = match self.config.transforms.clone() {
+            Some(names) => {
+                let mut transforms = Vec::new();
+                for name in names {
+                    let idx = self.transforms.iter()
+                        .position(|(x, _)| *x == name)
+                        .ok_or(Error::UnknownTransform(name))?;
+                    transforms.push(self.transforms.remove(idx));
+                }
+                transforms
+            }
+            None => self.transforms,
+        };
+
+        // Pick the rewriters the config asks for
+        let mut rewriters = Vec::new();
+        for name in &self.config.rewriters {
+            let rewriter = self.rewriters.remove(name)
+                .ok_or_else(|| Error::UnknownRewriter(name.clone()))?;
+            rewriters.push(ActiveRewriter::new(name.clone(), rewriter)?);
+        }
+
+        let data = data::load(
+            &self.config.content_path.join(&self.config.data_path))?;
+
+        let syntax_set = match self.syntax_set {
+            Some(syntax_set) => syntax_set,
+            None => {
+                // Load default syntaxes for syntax highlighting and convert
+                // it into a builder so we can add custom syntaxes to it
+                let mut ssb =
+                    SyntaxSet::load_defaults_newlines().into_builder();
+
+                // Add custom syntaxes from the `syntaxes` folder
+                if Path::new("syntaxes").is_dir() {
+                    ssb.add_from_folder("syntaxes", true)
+                        .map_err(Error::LoadSyntax)?;
+                }
+
+                ssb.build()
+            }
+        };
+
+        let theme = match self.theme {
+            Some(theme) => theme,
+            None => ThemeSet::load_defaults().themes
+                .remove(&self.config.syntax_theme)
+                .ok_or_else(|| Error::UnknownTheme(
+                    self.config.syntax_theme.clone()))?,
+        };
+
+        let cache = self.config.cache_path.as_ref().map(|x|
+            HandlerCache::new(x, &self.config, &theme, &syntax_set));
+
+        let website = Website {
+            syntax_set,
+            theme,
+            emoji_replacer: Replacer::new(),
+            handlers:       self.handlers,
+            transforms,
+            rewriters,
+            data,
+            header:         OnceLock::new(),
+            svg_symbols:    Mutex::new(BTreeMap::new()),
+            assets:         Mutex::new(BTreeMap::new()),
+            links:          Mutex::new(BTreeSet::new()),
+            outputs:        Mutex::new(BTreeMap::new()),
+            page_queue:     Mutex::new(PageQueue::default()),
+            warnings:       Mutex::new(Vec::new()),
+            dependencies:   Mutex::new(BTreeMap::new()),
+            extra_outputs:  Mutex::new(Vec::new()),
+            page_sources:   Mutex::new(HashMap::new()),
+            cache,
+            options:        self.options,
+            config:         self.config,
+        };
+        for path in plugin_files {
+            website.track_asset(path, AssetUsage::Embedded);
+        }
+        Ok(website)
+    }
+}
+
+impl Website {
+    /// Generate the whole website into `config.output_path`
+    pub async fn build(self) -> Result<BuildReport> {
+        let mut report = BuildReport::default();
+
+        // Wrap up the website in an `Arc` for sharing between threads
+        let website = Arc::new(self);
+
+        // Load the header file
+        let header = website.process_md(website.config.content_path
+            .join(&website.config.header_file), None).await?;
+        website.header.get_or_init(|| header.0);
+
+        // Write the feeds of the collections, which queue their pages
+        collection::write_feeds(&website).await?;
+
+        // Generate the base content file, and everything it leads to
+        website.queue_page(website.config.content_path
+            .join(&website.config.base_file));
+        loop {
+            let page = website.page_queue.lock().unwrap().pending.pop();
+            match page {
+                Some(page) => {
+                    report.pages.push(website.process_file(page).await?.0);
+                }
+                None => break,
+            }
+        }
+
+        // Ship the assets which were linked to
+        let assets = std::mem::take(&mut *website.assets.lock().unwrap());
+        report.assets = assets::copy_assets(&assets,
+            &website.config.content_path, &website.config.output_path).await?;
+
+        // Check that all internal links point to something we generated
+        let mut outputs =
+            std::mem::take(&mut *website.outputs.lock().unwrap());
+        let content_path = assets::normalize_path(&website.config.content_path);
+        for (asset, _) in assets.iter()
+                .filter(|x| *x.1 == AssetUsage::Copied) {
+            if let Ok(asset) = asset.strip_prefix(&content_path) {
+                outputs.insert(links::url_path(asset), BTreeSet::new());
+            }
+        }
+        let links = std::mem::take(&mut *website.links.lock().unwrap());
+        for link in links {
+            if let Some(problem) = links::check_url(&link.url, &outputs) {
+                report.broken_links.push(BrokenLink { link, problem });
+            }
+        }
+
+        // Find files which nothing referenced
+        if website.options.report_unused {
+            report.unused = assets::unused_files(&assets,
+                &website.config.content_path).await?;
+        }
+
+        // Hand back what the handlers told us
+        report.pages.append(&mut website.extra_outputs.lock().unwrap());
+        report.warnings = std::mem::take(&mut website.warnings.lock().unwrap());
+        report.dependencies =
+            std::mem::take(&mut website.dependencies.lock().unwrap());
+        report.cached = website.cache.as_ref().map(|x| x.hits()).unwrap_or(0);
+
+        Ok(report)
+    }
+
+    /// Get the HTML of the processed header, which is empty until the build
+    /// has rendered it
+    pub fn header(&self) -> &str {
+        self.header.get().map(|x| x.as_str()).unwrap_or("")
+    }
+
+    /// Request that the markdown at `path` gets generated into a page. Pages
+    /// are only generated once, no matter how many times they're queued
+    pub fn queue_page(&self, path: impl AsRef<Path>) {
+        let path = assets::normalize_path(path);
+        let mut queue = self.page_queue.lock().unwrap();
+        if queue.queued.insert(path.clone()) {
+            queue.pending.push(path);
+        }
+    }
+
+    /// Get the path of the generated HTML for the markdown at `path` with
+    /// the metadata `template_info`, relative to `output_path`, and its URL.
+    /// Markdown without metadata keeps its place in the content
+    /// Eg. `content/blog/x.md` -> `blog/x.html` and `/blog/x.html`
+    pub(crate) fn output_file(&self, path: impl AsRef<Path>,
+            template_info: Option<&TemplateInfo>) -> Result<(PathBuf, String)> {
+        let path = path.as_ref();
+        let relative = assets::normalize_path(path)
+            .strip_prefix(assets::normalize_path(&self.config.content_path))
+            .map_err(|x| Error::StripPrefix(path.to_path_buf(), x))?
+            .to_path_buf();
+        let Some(template_info) = template_info else {
+            let file = relative.with_extension("html");
+            let url = format!("/{}", links::url_path(&file));
+            return Ok((file, url));
+        };
+
+        // The page's own permalink wins over its collection's
+        let pattern = template_info.permalink.as_deref()
+            .or_else(|| collection::containing(&self.config, path)
+                .and_then(|x| x.permalink.as_deref()))
+            .unwrap_or(permalink::DEFAULT);
+        permalink::expand(pattern, &relative, template_info)
+            .map_err(|x| Error::BadPermalink(path.to_path_buf(), x))
+    }
+
+    /// Get the URL of the generated HTML for the markdown at `path`, which
+    /// depends on its `templateinfo`
+    /// Eg. `content/blog/x.md` -> `/blog/x.html`
+    pub async fn output_url(&self, path: impl AsRef<Path>) -> Result<String> {
+        let template_info = self.read_template_info(&path).await?;
+        Ok(self.output_file(path, template_info.as_ref())?.1)
+    }
+
+    /// If `dest`, which was found in the markdown at `source`, is a local link
+    /// to another markdown file, get the URL of the page the markdown becomes.
+    /// The linked markdown is queued to make sure the page gets generated
+    pub(crate) async fn md_link_url(&self, source: &Path, dest: &str)
+            -> Result<Option<String>> {
+        let (file, fragment) = match dest.find('#') {
+            Some(idx) => (&dest[..idx], &dest[idx..]),
+            None      => (dest, ""),
+        };
+        if !assets::is_local(file) ||
+                !file.to_ascii_lowercase().ends_with(".md") {
+            return Ok(None);
+        }
+
+        // Make sure the markdown we're linking to actually exists
+        let target =
+            assets::resolve_local(&self.config.content_path, source, file);
+        if !tokio::fs::metadata(&target).await.map(|x| x.is_file())
+                .unwrap_or(false) {
+            return Err(Error::MissingLinkTarget(source.to_path_buf(),
+                dest.to_string()));
+        }
+
+        self.queue_page(&target);
+        Ok(Some(format!("{}{}", self.output_url(&target).await?, fragment)))
+    }
+
+    /// Record all the internal links in `html`, which came from line `line`
+    /// of `source` and ends up on the page at `base_url`
+    pub(crate) fn record_links(&self, source: &Path, line: usize,
+            base_url: &str, html: &str) {
+        for (offset, dest) in links::find_urls(html) {
+            self.record_link(source, line + links::line_of(html, offset) - 1,
+                base_url, dest);
+        }
+    }
+
+    /// Record a link to `dest`, on line `line` of `source`, if it's internal
+    pub(crate) fn record_link(&self, source: &Path, line: usize, base_url: &str,
+            dest: &str) {
+        if let Some(url) = links::resolve_url(base_url, dest) {
+            self.links.lock().unwrap().insert(links::Link {
+                source: source.to_path_buf(),
+                dest: dest.to_string(),
+                line,
+                url,
+            });
+        }
+    }
+
+    /// Record that `path` is referenced by the website. Assets which are
+    /// `AssetUsage::Copied` will be copied to the output directory
+    pub(crate) fn track_asset(&self, path: impl AsRef<Path>,
+            usage: AssetUsage) {
+        let mut assets = self.assets.lock().unwrap();
+        let entry = assets.entry(assets::normalize_path(path))
+            .or_insert(usage);
+        *entry = (*entry).max(usage);
+    }
+
+    /// Load an asset from disk
+    pub async fn read_to_base64(&self, path: impl AsRef<Path>)
+            -> Result<String> {
+        // Read the image data
+        let path = self.config.content_path.join(path);
+        self.track_asset(&path, AssetUsage::Embedded);
+        let image = tokio::fs::read(&path).await
+            .map_err(|x| Error::ReadBase64Asset(path.clone(), x))?;
+        
+        // Convert to base64
+        Ok(base64::encode(image))
+    }
+
+    /// Encapsulate an asset on disk into an HTML-embedded base64 image. SVGs
+    /// are instead inlined with [`Website::load_svg`], titled by their file
+    /// name
+    pub async fn load_asset(&self, path: impl AsRef<Path>) -> Result<String> {
+        let path = path.as_ref();
+        if path.extension().map(|x| x.eq_ignore_ascii_case("svg")) ==
+                Some(true) {
+            let title = path.file_stem().unwrap_or_default().to_string_lossy();
+            return self.load_svg(path, &title).await;
+        }
+
+        // Read the image data
+        let path = self.config.content_path.join(path);
+        self.track_asset(&path, AssetUsage::Embedded);
+        let image = tokio::fs::read(&path).await
+            .map_err(|x| Error::ReadBase64Asset(path.clone(), x))?;
+        
+        // Create image string. We don't use a format string here so that we
+        // can use `encode_config_buf` without performing an extra allocation
+        let mut buf = String::new();
+        buf += "<img src=\"data:";
+        buf += mime_guess::from_path(&path).first_raw().unwrap();
+        buf += ";base64,";
+
+        // Encode image
+        base64::encode_config_buf(image, base64::STANDARD, &mut buf);
+
+        // Finish the image string
+        buf += "\" />";
+        Ok(buf)
+    }
+    
+    /// Inline an SVG asset on disk as a `<svg>` with an accessible `title`.
+    /// The sanitized SVG is de-duplicated into a `<symbol>` which the page's
+    /// sprite provides, and is referenced via `<use>`
+    pub async fn load_svg(&self, path: impl AsRef<Path>, title: &str)
+            -> Result<String> {
+        let id = svg::symbol_id(path.as_ref());
+        self.track_asset(self.config.content_path.join(&path),
+            AssetUsage::Embedded);
+        if let Some(symbol) = self.svg_symbols.lock().unwrap().get(&id) {
+            return Ok(symbol.reference(title));
+        }
+
+        // Read and sanitize the SVG
+        let path = self.config.content_path.join(path);
+        let contents = tokio::fs::read_to_string(&path).await
+            .map_err(|x| Error::ReadSvg(path.clone(), x))?;
+        let symbol = svg::Symbol::parse(&path, id.clone(), &contents)?;
+
+        let reference = symbol.reference(title);
+        self.svg_symbols.lock().unwrap().insert(id, symbol);
+        Ok(reference)
+    }
+
+    /// Look up the dotted `key` in the data files, eg. `talks.0.title`,
+    /// recording that the block described by `ctx` depends on the file. See
+    /// `config.data_path`
+    pub fn data(&self, ctx: &mut HandlerContext, key: &str)
+            -> Option<&serde_json::Value> {
+        let (path, value) = data::lookup(&self.data, key)?;
+        ctx.add_dependency(path);
+        Some(value)
+    }
+
+    /// Get the URL and metadata of the pages in the collection `name` which
+    /// `query` picks, recording that the block described by `ctx` depends on
+    /// them and making sure they get generated. See `config.collections`
+    pub async fn collection(self: &Arc<Self>, ctx: &mut HandlerContext<'_>,
+            name: &str, query: &CollectionQuery)
+            -> Result<Vec<(String, TemplateInfo)>> {
+        collection::query(self, ctx, name, query).await
+    }
+
+    /// Convert `code` into a code block, highlighted as `lang` if we have a
+    /// syntax for it
+    pub fn highlight_code(&self, code: &str, lang: Option<&str>) -> String {
+        if let Some(syntax) =
+                lang.and_then(|x| self.syntax_set.find_syntax_by_token(x)) {
+            return syntect::html::highlighted_html_for_string(
+                code, &self.syntax_set, syntax, &self.theme);
+        }
+
+        let mut html = String::from("<pre><code>");
+        escape_html(&mut html, code).unwrap();
+        html += "</code></pre>\n";
+        html
+    }
+
+    /// Record that `file`, relative to `config.output_path`, is generated
+    /// from `source`, failing if something else already generates it
+    pub(crate) fn claim_output(&self, file: &Path, source: &Path)
+            -> Result<()> {
+        permalink::claim(&mut self.page_sources.lock().unwrap(), file, source)
+            .map_err(|other| Error::PageCollision(file.to_path_buf(), other,
+                source.to_path_buf()))
+    }
+
+    /// Whether there's a handler named `name`
+    pub(crate) fn has_handler(&self, name: &str) -> bool {
+        self.handlers.contains_key(name)
+    }
+
+    /// Invoke the handler `name` on `input`, for the block or shortcode `ctx`
+    /// describes in the markdown from `source`. Everything the handler reports
+    /// is applied to the website, and markdown it returns is rendered
+    pub(crate) async fn run_handler(self: &Arc<Self>, name: &str, input: &str,
+            mut ctx: HandlerContext<'_>, source: &Source<'_>,
+            heading_ids: &mut HashMap<String, usize>) -> Result<String> {
+        let handler = self.handlers.get(name).ok_or_else(|| {
+            Error::MissingHandler(ctx.source.to_path_buf(), name.into())
+        })?;
+
+        let line = *ctx.lines.start();
+        let inline = ctx.inline;
+
+        // Use the output from a previous build if nothing changed
+        let cache = self.cache.as_ref().filter(|_| handler.cacheable())
+            .map(|cache| (cache, cache.key(name, input, &ctx)));
+        let cached = match &cache {
+            Some((cache, key)) => cache.get(key).await,
+            None => None,
+        };
+        let output = match cached {
+            Some(entry) => entry.replay(&mut ctx),
+            None => {
+                let output = handler.handle(input, self, &mut ctx).await?;
+                if let Some((cache, key)) = &cache {
+                    cache.put(key, &ctx, &output).await?;
+                }
+                output
+            }
+        };
+        let markdown = ctx.markdown;
+        self.apply_context(ctx).await?;
+
+        if markdown {
+            // Handlers returning markdown which invokes handlers could go on
+            // forever
+            if source.depth >= MAX_HANDLER_DEPTH {
+                return Err(Error::HandlerDepth(source.path.to_path_buf(),
+                    line));
+            }
+
+            // Everything in the markdown is attributed to the block
+            let nested = Source {
+                block_line: Some(line),
+                depth:      source.depth + 1,
+                ..*source
+            };
+            let html =
+                self.render_markdown(&output, &nested, heading_ids).await?;
+
+            // Shortcodes are already in a paragraph, so don't start another
+            if inline && html.matches("<p>").count() == 1 {
+                if let Some(html) = html.strip_prefix("<p>")
+                        .and_then(|x| x.strip_suffix("</p>\n")) {
+                    return Ok(html.to_string());
+                }
+            }
+            return Ok(html);
+        }
+
+        // Links in the output are attributed to the start of the block, as
+        // the HTML lines have nothing to do with the markdown lines
+        for (_, dest) in links::find_urls(&output) {
+            self.record_link(source.path, line, source.base_url, dest);
+        }
+        Ok(output)
+    }
+
+    /// Apply everything a handler reported in `ctx` to the website
+    pub(crate) async fn apply_context(&self, ctx: HandlerContext<'_>)
+            -> Result<()> {
+        let source = ctx.source.to_path_buf();
+        for message in ctx.warnings {
+            self.warnings.lock().unwrap().push(Warning {
+                source: source.clone(),
+                line: *ctx.lines.start(),
+                message,
+            });
+        }
+
+        // Anything the output depends on is used by the website
+        for dependency in &ctx.dependencies {
+            self.track_asset(dependency, AssetUsage::Embedded);
+        }
+        self.dependencies.lock().unwrap().entry(source).or_default()
+            .extend(ctx.dependencies);
+        for asset in ctx.assets {
+            self.track_asset(asset, AssetUsage::Copied);
+        }
+        for page in ctx.pages {
+            self.queue_page(page);
+        }
+
+        for (output_file, contents) in ctx.outputs {
+            let output_path = self.config.output_path.join(&output_file);
+            let out_parent_dir = output_path.parent().unwrap();
+            tokio::fs::create_dir_all(out_parent_dir).await
+                .map_err(|x|
+                    Error::CreateOutputDir(out_parent_dir.to_path_buf(), x))?;
+            tokio::fs::write(&output_path, &contents).await
+                .map_err(|x| Error::WriteOutput(output_path.clone(), x))?;
+
+            // Make the output available to link to
+            self.outputs.lock().unwrap().insert(links::url_path(&output_file),
+                links::find_ids(&String::from_utf8_lossy(&contents)));
+            self.extra_outputs.lock().unwrap().push(output_path);
+        }
+
+        Ok(())
+    }
+
+    /// Read the metadata in the `templateinfo` blocks of the markdown at
+    /// `path`, if it has any, without rendering it
+    pub(crate) async fn read_template_info(&self, path: impl AsRef<Path>)
+            -> Result<Option<TemplateInfo>> {
+        let markdown = tokio::fs::read_to_string(&path).await
+            .map_err(|x|
+                Error::ReadMarkdownInput(path.as_ref().to_path_buf(), x))?;
+        self.extract_template_info(path.as_ref(), &markdown)
+    }
+
+    /// Get the metadata in the `templateinfo` blocks of the `markdown` at
+    /// `path`, if it has any
+    fn extract_template_info(&self, path: &Path, markdown: &str)
+            -> Result<Option<TemplateInfo>> {
+        let mut template_toml = None;
+        let mut in_template_info = false;
+        for event in Parser::new(markdown) {
+            match event {
+                Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(lang))) => {
+                    in_template_info = lang.as_ref() == "templateinfo";
+                }
+                Event::End(Tag::CodeBlock(_)) => in_template_info = false,
+                Event::Text(text) if in_template_info => {
+                    template_toml.get_or_insert_with(String::new)
+                        .push_str(&text);
+                }
+                _ => {}
+            }
+        }
+        template_toml.map(|x| self.parse_template_info(path, &x)).transpose()
+    }
+
+    /// Parse the contents of a `templateinfo` block found in `path`
+    fn parse_template_info(&self, path: &Path, toml: &str)
+            -> Result<TemplateInfo> {
+        let mut table = toml::from_str::<toml::value::Table>(toml)
+            .map_err(|x| Error::ParseTemplateInfo(path.to_path_buf(), x))?;
+
+        // Pages in a collection default to its template and style
+        if let Some(collection) = collection::containing(&self.config, path) {
+            for (key, default) in [("template", &collection.template),
+                    ("style", &collection.style)] {
+                if let Some(default) = default {
+                    table.entry(key).or_insert_with(||
+                        default.to_string_lossy().into_owned().into());
+                }
+            }
+        }
+        let mut template_info = toml::Value::Table(table)
+            .try_into::<TemplateInfo>()
+            .map_err(|x| Error::ParseTemplateInfo(path.to_path_buf(), x))?;
+        
+        // Make paths relative to content path
+        template_info.style =
+            self.config.content_path.join(template_info.style);
+        template_info.template =
+            self.config.content_path.join(template_info.template);
+
+        Ok(template_info)
+    }
+
+    /// Convert the `path` markdown into HTML without encapsulating it in the
+    /// templates. This just gives the raw internal HTML of the markdown.
+    /// If `parent` is specified, the markdown is being rendered as part of
+    /// the handler block that `parent` describes
+    pub async fn process_md(self: &Arc<Self>, path: impl AsRef<Path>,
+            parent: Option<&HandlerContext<'_>>)
+            -> Result<(String, TemplateInfo)> {
+        let (html, template_info) =
+            self.process_fragment(&path, parent).await?;
+        let template_info = template_info.ok_or_else(|| {
+            Error::TemplateInfoMissing(path.as_ref().to_path_buf())
+        })?;
+        Ok((html, template_info))
+    }
+
+    /// Convert the `path` markdown into HTML, like [`Website::process_md`],
+    /// but without requiring a `templateinfo` section
+    pub async fn process_fragment(self: &Arc<Self>, path: impl AsRef<Path>,
+            parent: Option<&HandlerContext<'_>>)
+            -> Result<(String, Option<TemplateInfo>)> {
+        // Read the markdown input
+        self.track_asset(&path, AssetUsage::Embedded);
+        let markdown_input = tokio::fs::read_to_string(&path).await
+            .map_err(|x|
+                Error::ReadMarkdownInput(path.as_ref().to_path_buf(), x))?;
+
+        // Add ourselves to the stack of markdown being rendered
+        let mut include_stack = parent.map(|x| x.include_stack().to_vec())
+            .unwrap_or_default();
+        include_stack.push(tokio::fs::canonicalize(&path).await
+            .map_err(|x|
+                Error::ReadMarkdownInput(path.as_ref().to_path_buf(), x))?);
+
+        // Extract the template metadata up front, so handlers have access to
+        // it no matter where it is in the markdown
+        let template_info =
+            self.extract_template_info(path.as_ref(), &markdown_input)?;
+
+        // The URL this markdown will be at, used to resolve relative links
+        let base_url = self.output_file(&path, template_info.as_ref())?.1;
+
+        // The URL of the page we're rendering for
+        let page_url = parent.map(|x| x.page_url.to_string())
+            .unwrap_or_else(|| base_url.clone());
+
+        // The metadata of the page we're rendering for
+        let page = match parent {
+            Some(parent) => parent.page,
+            None         => template_info.as_ref(),
+        };
+
+        let source = Source {
+            path:          path.as_ref(),
+            base_url:      &base_url,
+            page_url:      &page_url,
+            include_stack: &include_stack,
+            block_line:    None,
+            depth:         0,
+            page,
+        };
+        let markdown_html = self.render_markdown(&markdown_input, &source,
+            &mut HashMap::new()).await?;
+
+        Ok((markdown_html, template_info))
+    }
+
+    /// Render the `markdown` from `source` into HTML, transforming it along
+    /// the way. `heading_ids` counts the ids given to headings so far, which
+    /// are kept unique across everything rendered for the file
+    fn render_markdown<'a>(self: &'a Arc<Self>, markdown: &'a str,
+            source: &'a Source<'a>,
+            heading_ids: &'a mut HashMap<String, usize>)
+            -> Pin<Box<dyn Future<Output = Result<String>> + Send + 'a>> {
+        // Handlers can return markdown, which is rendered by this, so this is
+        // recursive and must be boxed
+        Box::pin(self.render_events(markdown, source, heading_ids))
+    }
+
+    /// Implementation of [`Website::render_markdown`]
+    async fn render_events(self: &Arc<Self>, markdown: &str,
+            source: &Source<'_>, heading_ids: &mut HashMap<String, usize>)
+            -> Result<String> {
+        // String to hold the HTML output from the markdown
+        let mut markdown_html = String::new();
+
+        // Parse the markdown
+        let mut events = Parser::new(markdown).into_offset_iter()
+            .collect::<Vec<_>>();
+
+        // Run the events through the transforms, in order
+        let mut ctx = TransformContext::new(markdown, source, heading_ids);
+        for (_, transform) in &self.transforms {
+            events = transform.transform(events, self, &mut ctx).await?;
+        }
+
+        // Conver the markdown into HTML
+        html::push_html(&mut markdown_html,
+            events.into_iter().map(|(event, _)| event));
+
+        Ok(markdown_html)
+    }
+
+    /// Convert the `path` markdown into HTML
+    pub async fn process_file(self: &Arc<Self>, path: impl AsRef<Path>)
+            -> Result<(PathBuf, TemplateInfo)> {
+        // Convert markdown to HTML
+        let (markdown_html, template_info) =
+            self.process_md(&path, None).await?;
+
+        // Construct the output path for the generated HTML
+        // Eg. `content/index.md` -> `output/index.html`
+        let (output_file, page_url) =
+            self.output_file(&path, Some(&template_info))?;
+        let output_path = self.config.output_path.join(&output_file);
+
+        // Two pages in the same place would overwrite each other
+        self.claim_output(&output_file, path.as_ref())?;
+
+        // Create the output directories needed to create the output file
+        let out_parent_dir = output_path.parent().unwrap();
+        tokio::fs::create_dir_all(out_parent_dir).await
+            .map_err(|x| 
+                Error::CreateOutputDir(out_parent_dir.to_path_buf(), x))?;
+
+        // Read the CSS
+        self.track_asset(&template_info.style, AssetUsage::Embedded);
+        let css = tokio::fs::read_to_string(&template_info.style).await
+            .map_err(|x| Error::ReadStyle(path.as_ref().to_path_buf(),
+                template_info.style.clone(), x))?;
+        
+        // Read the HTML
+        self.track_asset(&template_info.template, AssetUsage::Embedded);
+        let html = tokio::fs::read_to_string(&template_info.template).await
+            .map_err(|x| Error::ReadTemplate(path.as_ref().to_path_buf(),
+                template_info.template.clone(), x))?;
+
+        // Fill in the data the template uses, which the page then depends on
+        let (html, used) =
+            data::fill_template(&self.data, path.as_ref(), &html)?;
+        for data_path in &used {
+            self.track_asset(data_path, AssetUsage::Embedded);
+        }
+        self.dependencies.lock().unwrap()
+            .entry(path.as_ref().to_path_buf()).or_default().extend(used);
+
+        // Fill in the collections the template lists
+        let mut ctx = HandlerContext::new(path.as_ref(), &page_url,
+            Some(&template_info), 1..=1, false, &[]);
+        let html = collection::fill_template(self, &mut ctx, &html).await?;
+        self.apply_context(ctx).await?;
+        self.record_links(&template_info.template, 1, &page_url, &html);
+        
+        // Read the favicon
+        let favicon = self.read_to_base64(&template_info.favicon).await?;
+
+        // Very high quality templating
+        let html = html.replace("<<<PUT THE STYLESHEET HERE>>>", &css);
+        let html = html.replace("<<<PUT THE MAIN CONTENT HERE>>>", &markdown_html);
+        let html = html.replace("<<<PUT THE HEADER HERE>>>", self.header());
+        let html = html.replace("<<<PUT THE FAVICON HERE>>>", &favicon);
+        let html = html.replace("<<<PUT THE TITLE HERE>>>", &template_info.title);
+        let html = html.replace("<<<PUT THE DESCRIPTION HERE>>>",
+            &template_info.description);
+
+        // Provide the SVG symbols which this page references
+        let sprite = svg::sprite(&self.svg_symbols.lock().unwrap(), &html);
+        let html = html.replace("<<<PUT THE SVG SPRITE HERE>>>", &sprite);
+
+        // Touch up the final page
+        let html = rewrite::rewrite(&self.rewriters, &html, &RewriteContext {
+            source:   path.as_ref(),
+            page_url: &page_url,
+        })?;
+
+        // Write the output!
+        tokio::fs::write(&output_path, html.as_bytes()).await
+            .map_err(|x| Error::WriteOutput(output_path.clone(), x))?;
+
+        // Save the ids on the page so links to anchors can be checked
+        self.outputs.lock().unwrap().insert(links::url_path(&output_file),
+            links::find_ids(&html));
+        
+        Ok((output_path, template_info))
+    }
+}